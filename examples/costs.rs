@@ -0,0 +1,11 @@
+use rust_bitcoin_u31_or_u30::{costs_to_csv, costs_to_json, primitive_costs};
+
+// Prints the size and the peak stack usage of the primitives, as CSV or, with --json, as JSON.
+fn main() {
+    let costs = primitive_costs();
+    if std::env::args().any(|arg| arg == "--json") {
+        print!("{}", costs_to_json(&costs));
+    } else {
+        print!("{}", costs_to_csv(&costs));
+    }
+}
@@ -0,0 +1,389 @@
+use crate::{
+    u31_add, u31_mul, u31_mul_by_constant, u31_sub, u31ext_add, u31ext_equalverify_one, u31ext_mul,
+    u31ext_mul_u31, u31ext_mul_u31_by_constant, u31ext_sub, FieldScript, U31ExtConfig,
+};
+use bitvm::treepp::*;
+use std::marker::PhantomData;
+
+// A variable of a FieldBuilder, either a base field (u31) or an extension field (u31ext) element
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FieldVar(usize);
+
+enum Op {
+    Add(FieldVar, FieldVar),
+    Sub(FieldVar, FieldVar),
+    Mul(FieldVar, FieldVar),
+    MulConst(FieldVar, u32),
+    // checks that the second variable, a hint, is the inverse of the first one
+    Inv(FieldVar, FieldVar),
+}
+
+// Composes field arithmetic over named variables instead of stack positions.
+//
+// The operations are only recorded; finish() lays them out in order, bringing each operand to
+// the top with OP_PICK, or with OP_ROLL at its last use, so that values die as soon as they are
+// no longer needed. Whatever is left besides the outputs is dropped at the end.
+//
+// The script takes the inputs in the order they are declared, the first one at the bottom. This
+// includes the hints of inv(), which are declared when inv() is called.
+pub struct FieldBuilder<C: U31ExtConfig> {
+    // the number of stack elements of each variable, 1 or DEGREE
+    sizes: Vec<usize>,
+    inputs: Vec<FieldVar>,
+    ops: Vec<(Op, Option<FieldVar>)>,
+    _marker: PhantomData<C>,
+}
+
+impl<C: U31ExtConfig> Default for FieldBuilder<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: U31ExtConfig> FieldBuilder<C> {
+    pub fn new() -> Self {
+        Self {
+            sizes: vec![],
+            inputs: vec![],
+            ops: vec![],
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn base_input(&mut self) -> FieldVar {
+        let var = self.new_var(1);
+        self.inputs.push(var);
+        var
+    }
+
+    pub fn ext_input(&mut self) -> FieldVar {
+        let var = self.new_var(C::DEGREE as usize);
+        self.inputs.push(var);
+        var
+    }
+
+    pub fn add(&mut self, a: FieldVar, b: FieldVar) -> FieldVar {
+        assert_eq!(self.size(a), self.size(b));
+        self.push_op(Op::Add(a, b), self.size(a))
+    }
+
+    pub fn sub(&mut self, a: FieldVar, b: FieldVar) -> FieldVar {
+        assert_eq!(self.size(a), self.size(b));
+        self.push_op(Op::Sub(a, b), self.size(a))
+    }
+
+    // either operand can be a base field element
+    pub fn mul(&mut self, a: FieldVar, b: FieldVar) -> FieldVar {
+        let size = self.size(a).max(self.size(b));
+        self.push_op(Op::Mul(a, b), size)
+    }
+
+    pub fn mul_const(&mut self, a: FieldVar, constant: u32) -> FieldVar {
+        self.push_op(Op::MulConst(a, constant), self.size(a))
+    }
+
+    // The inverse is a new input supplied by the prover, checked with one multiplication.
+    pub fn inv(&mut self, a: FieldVar) -> FieldVar {
+        let hint = self.new_var(self.size(a));
+        self.inputs.push(hint);
+        self.ops.push((Op::Inv(a, hint), None));
+        hint
+    }
+
+    pub fn inputs(&self) -> &[FieldVar] {
+        &self.inputs
+    }
+
+    pub fn finish(self, outputs: &[FieldVar]) -> FieldScript {
+        // the operands of all operations in order, followed by the outputs
+        let mut uses = vec![];
+        for (op, _) in self.ops.iter() {
+            uses.extend(self.operands(op));
+        }
+        uses.extend_from_slice(outputs);
+        let mut last_use = vec![None; self.sizes.len()];
+        for (i, var) in uses.iter().enumerate() {
+            last_use[var.0] = Some(i);
+        }
+
+        let mut stack = self.inputs.clone();
+        let mut script_bytes = vec![];
+        let mut step = 0;
+
+        for (op, result) in self.ops.iter() {
+            let operands = self.operands(op);
+            for var in operands.iter() {
+                let keep = last_use[var.0] != Some(step);
+                script_bytes
+                    .extend_from_slice(self.bring_to_top(&mut stack, *var, keep).as_bytes());
+                step += 1;
+            }
+            script_bytes.extend_from_slice(self.op_script(op).as_bytes());
+            stack.truncate(stack.len() - operands.len());
+            if let Some(result) = result {
+                stack.push(*result);
+            }
+        }
+
+        // move the outputs to the altstack, drop everything else and bring the outputs back
+        let num_inputs = self.inputs.iter().map(|var| self.size(*var)).sum();
+        let num_outputs = outputs.iter().map(|var| self.size(*var)).sum();
+        if stack != outputs {
+            for (i, var) in outputs.iter().enumerate().rev() {
+                let keep = outputs[..i].contains(var);
+                script_bytes
+                    .extend_from_slice(self.bring_to_top(&mut stack, *var, keep).as_bytes());
+                stack.pop();
+                script_bytes.extend_from_slice(
+                    script! {
+                        for _ in 0..self.size(*var) {
+                            OP_TOALTSTACK
+                        }
+                    }
+                    .as_bytes(),
+                );
+            }
+
+            let num_dead: usize = stack.iter().map(|var| self.size(*var)).sum();
+            script_bytes.extend_from_slice(
+                script! {
+                    for _ in 0..num_dead / 2 {
+                        OP_2DROP
+                    }
+                    for _ in 0..num_dead % 2 {
+                        OP_DROP
+                    }
+                    for _ in 0..num_outputs {
+                        OP_FROMALTSTACK
+                    }
+                }
+                .as_bytes(),
+            );
+        }
+
+        FieldScript::new(Script::from(script_bytes), num_inputs, num_outputs)
+    }
+
+    fn new_var(&mut self, size: usize) -> FieldVar {
+        self.sizes.push(size);
+        FieldVar(self.sizes.len() - 1)
+    }
+
+    fn size(&self, var: FieldVar) -> usize {
+        self.sizes[var.0]
+    }
+
+    fn push_op(&mut self, op: Op, size: usize) -> FieldVar {
+        let result = self.new_var(size);
+        self.ops.push((op, Some(result)));
+        result
+    }
+
+    // the variables that the operation takes from the stack, the topmost last
+    fn operands(&self, op: &Op) -> Vec<FieldVar> {
+        match op {
+            Op::Add(a, b) | Op::Sub(a, b) | Op::Inv(a, b) => vec![*a, *b],
+            Op::Mul(a, b) => {
+                if self.size(*a) == 1 && self.size(*b) > 1 {
+                    vec![*b, *a]
+                } else {
+                    vec![*a, *b]
+                }
+            }
+            Op::MulConst(a, _) => vec![*a],
+        }
+    }
+
+    fn op_script(&self, op: &Op) -> Script {
+        let is_base = |var: &FieldVar| self.size(*var) == 1;
+
+        match op {
+            Op::Add(a, _) if is_base(a) => u31_add::<C::BaseFieldConfig>(),
+            Op::Add(_, _) => u31ext_add::<C>(),
+            Op::Sub(a, _) if is_base(a) => u31_sub::<C::BaseFieldConfig>(),
+            Op::Sub(_, _) => u31ext_sub::<C>(),
+            Op::Mul(a, b) if is_base(a) && is_base(b) => u31_mul::<C::BaseFieldConfig>(),
+            Op::Mul(a, b) if is_base(a) || is_base(b) => u31ext_mul_u31::<C>(),
+            Op::Mul(_, _) => u31ext_mul::<C>(),
+            Op::MulConst(a, constant) if is_base(a) => {
+                u31_mul_by_constant::<C::BaseFieldConfig>(*constant)
+            }
+            Op::MulConst(_, constant) => u31ext_mul_u31_by_constant::<C>(*constant),
+            Op::Inv(a, _) if is_base(a) => script! {
+                { u31_mul::<C::BaseFieldConfig>() }
+                1 OP_EQUALVERIFY
+            },
+            Op::Inv(_, _) => script! {
+                { u31ext_mul::<C>() }
+                { u31ext_equalverify_one::<C>() }
+            },
+        }
+    }
+
+    // Copies (if keep) or moves the variable to the top of the stack. The stack holds at most
+    // one live instance of a variable, below any copies made for the current operation.
+    fn bring_to_top(&self, stack: &mut Vec<FieldVar>, var: FieldVar, keep: bool) -> Script {
+        let position = stack.iter().position(|v| *v == var).unwrap();
+        let size = self.size(var);
+        let above: usize = stack[position + 1..].iter().map(|v| self.size(*v)).sum();
+        let depth = above + size - 1;
+
+        if keep {
+            stack.push(var);
+            script! {
+                for _ in 0..size {
+                    { pick(depth) }
+                }
+            }
+        } else {
+            stack.remove(position);
+            stack.push(var);
+            if above == 0 {
+                script! {}
+            } else {
+                script! {
+                    for _ in 0..size {
+                        { roll(depth) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn pick(depth: usize) -> Script {
+    match depth {
+        0 => script! { OP_DUP },
+        1 => script! { OP_OVER },
+        _ => script! { { depth } OP_PICK },
+    }
+}
+
+fn roll(depth: usize) -> Script {
+    match depth {
+        1 => script! { OP_SWAP },
+        2 => script! { OP_ROT },
+        _ => script! { { depth } OP_ROLL },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{u31ext_equalverify, BabyBear, BabyBear4, FieldBuilder, U31Config, QM31};
+    use bitvm::treepp::*;
+    use p3_field::extension::Complex;
+    use p3_field::{AbstractExtensionField, AbstractField, Field, PrimeField32};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    type F = p3_field::extension::BinomialExtensionField<Complex<p3_mersenne_31::Mersenne31>, 2>;
+
+    fn push(x: &F) -> Script {
+        let x: &[Complex<p3_mersenne_31::Mersenne31>] = x.as_base_slice();
+        script! {
+            { x[1].imag().as_canonical_u32() }
+            { x[1].real().as_canonical_u32() }
+            { x[0].imag().as_canonical_u32() }
+            { x[0].real().as_canonical_u32() }
+        }
+    }
+
+    fn from_base(x: p3_mersenne_31::Mersenne31) -> F {
+        F::from_base(Complex::new(x, p3_mersenne_31::Mersenne31::zero()))
+    }
+
+    #[test]
+    fn test_field_builder_ext() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0u64);
+
+        // out = (((a + b) * x) * a - b)^{-1} * 7, along with a
+        let mut builder = FieldBuilder::<QM31>::new();
+        let a = builder.ext_input();
+        let b = builder.ext_input();
+        let x = builder.base_input();
+        let c = builder.add(a, b);
+        let c = builder.mul(x, c);
+        let d = builder.mul(c, a);
+        let d = builder.sub(d, b);
+        let e = builder.inv(d);
+        let out = builder.mul_const(e, 7);
+        let fs = builder.finish(&[out, a]);
+        eprintln!("field builder example: {}", fs.script.len());
+        assert_eq!((fs.inputs, fs.outputs), (13, 8));
+        assert!(fs.is_balanced());
+
+        for _ in 0..10 {
+            let a = rng.gen::<F>();
+            let b = rng.gen::<F>();
+            let x = rng.gen::<p3_mersenne_31::Mersenne31>();
+            let d = (a + b) * from_base(x) * a - b;
+            let e = d.inverse();
+            let out = e * from_base(p3_mersenne_31::Mersenne31::from_canonical_u32(7));
+
+            let script = script! {
+                { push(&a) }
+                { push(&b) }
+                { x.as_canonical_u32() }
+                { push(&e) }
+                { fs.script.clone() }
+                { push(&a) }
+                { u31ext_equalverify::<QM31>() }
+                { push(&out) }
+                { u31ext_equalverify::<QM31>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            // a wrong inverse hint is rejected
+            let script = script! {
+                { push(&a) }
+                { push(&b) }
+                { x.as_canonical_u32() }
+                { push(&(e + F::one())) }
+                { fs.script.clone() }
+                for _ in 0..4 {
+                    OP_2DROP
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(!exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_field_builder_base() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        let modulus = BabyBear::MOD as u64;
+
+        // x * x + y, with z unused and x used twice in the same operation
+        let mut builder = FieldBuilder::<BabyBear4>::new();
+        let x = builder.base_input();
+        let y = builder.base_input();
+        let _z = builder.base_input();
+        let x2 = builder.mul(x, x);
+        let out = builder.add(x2, y);
+        let fs = builder.finish(&[out]);
+        assert_eq!((fs.inputs, fs.outputs), (3, 1));
+
+        for _ in 0..10 {
+            let x = prng.gen_range(0..BabyBear::MOD);
+            let y = prng.gen_range(0..BabyBear::MOD);
+            let z = prng.gen_range(0..BabyBear::MOD);
+            let out = ((x as u64 * x as u64 + y as u64) % modulus) as u32;
+
+            assert!(fs.check(&[x, y, z]));
+            let script = script! {
+                { x }
+                { y }
+                { z }
+                { fs.script.clone() }
+                { out }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+}
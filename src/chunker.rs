@@ -0,0 +1,344 @@
+use crate::{u31_commit_and_check, u31_commit_sign, FieldScript, U31Config};
+use bitvm::signatures::winternitz::PublicKey;
+use bitvm::treepp::*;
+use std::ops::Range;
+
+// A piece of a computation that fits in one tapscript. It takes the whole intermediate state
+// left by the previous chunk, `inputs` elements, and leaves `outputs` elements for the next one.
+pub struct Chunk {
+    pub script: Script,
+    // the steps of the computation that the chunk runs
+    pub steps: Range<usize>,
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+// Splits a computation, given as steps that start from `inputs` elements, into chunks of at
+// most `max_chunk_size` bytes (unless a single step is larger).
+//
+// A chunk can only end where the altstack is back to where it started. Among these points, it
+// ends at the one with the fewest elements on the stack, the later one on a tie, since each of
+// them is to be committed to.
+pub fn chunk_field_scripts(
+    steps: &[FieldScript],
+    inputs: usize,
+    max_chunk_size: usize,
+) -> Vec<Chunk> {
+    // the number of elements on the stack and the altstack before each step and at the end
+    let mut state = vec![(inputs, 0isize)];
+    for step in steps.iter() {
+        let (stack, altstack) = *state.last().unwrap();
+        assert!(
+            stack >= step.inputs,
+            "a step takes more elements than there are"
+        );
+        state.push((
+            stack - step.inputs + step.outputs,
+            altstack + step.altstack_delta,
+        ));
+    }
+    assert_eq!(state.last().unwrap().1, 0, "the altstack must end balanced");
+
+    let mut chunks = vec![];
+    let mut start = 0;
+    while start < steps.len() {
+        let mut end = start;
+        let mut size = 0;
+        while end < steps.len()
+            && (end == start || size + steps[end].script.len() <= max_chunk_size)
+        {
+            size += steps[end].script.len();
+            end += 1;
+        }
+
+        if end < steps.len() {
+            end = (start + 1..=end)
+                .filter(|i| state[*i].1 == 0)
+                .min_by_key(|i| (state[*i].0, usize::MAX - i))
+                .expect("no point in the chunk where the altstack is balanced");
+        }
+
+        let mut script_bytes = vec![];
+        for step in steps[start..end].iter() {
+            script_bytes.extend_from_slice(step.script.as_bytes());
+        }
+        chunks.push(Chunk {
+            script: Script::from(script_bytes),
+            steps: start..end,
+            inputs: state[start].0,
+            outputs: state[end].0,
+        });
+        start = end;
+    }
+    chunks
+}
+
+impl Chunk {
+    // Input: the signatures (see chunk_disprove_witness) of the state before and after the chunk
+    // Output: true if the chunk does not turn the committed inputs into the committed outputs
+    //
+    // This is the script that a challenger runs to disprove the outputs that the operator
+    // committed to. All the signatures must be valid, each element being committed to under its
+    // own public key (see commit_public_key). An element that is not canonical, in the inputs or
+    // the outputs, disproves the chunk by itself: it runs as 0 and the script leaves true.
+    pub fn disprove_script<M: U31Config>(
+        &self,
+        input_public_keys: &[PublicKey],
+        output_public_keys: &[PublicKey],
+    ) -> Script {
+        assert_eq!(input_public_keys.len(), self.inputs);
+        assert_eq!(output_public_keys.len(), self.outputs);
+        assert!(self.outputs >= 1);
+
+        script! {
+            // whether an element is not canonical, kept on top of the altstack
+            0 OP_TOALTSTACK
+            for public_key in output_public_keys.iter().chain(input_public_keys.iter().rev()) {
+                { u31_commit_and_check::<M>(public_key) }
+                OP_FROMALTSTACK OP_BOOLOR
+                OP_SWAP OP_TOALTSTACK
+                OP_TOALTSTACK
+            }
+            OP_FROMALTSTACK
+            for _ in 0..self.inputs {
+                OP_FROMALTSTACK
+            }
+
+            { self.script.clone() }
+
+            OP_FROMALTSTACK OP_NUMNOTEQUAL
+            for _ in 1..self.outputs {
+                OP_SWAP OP_FROMALTSTACK OP_NUMNOTEQUAL OP_BOOLOR
+            }
+            OP_BOOLOR
+        }
+    }
+}
+
+// The witness of Chunk::disprove_script, with the elements of each state in stack order (the
+// last one on top) and one secret key per element
+//
+// The signatures are laid out in the order in which disprove_script verifies them: the outputs
+// first, the first one on top, then the inputs, the last one first.
+pub fn chunk_disprove_witness(
+    input_secret_keys: &[String],
+    inputs: &[u32],
+    output_secret_keys: &[String],
+    outputs: &[u32],
+) -> Script {
+    script! {
+        for (secret_key, x) in input_secret_keys.iter().zip(inputs.iter()) {
+            { u31_commit_sign(secret_key, *x) }
+        }
+        for (secret_key, x) in output_secret_keys.iter().zip(outputs.iter()).rev() {
+            { u31_commit_sign(secret_key, *x) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        chunk_disprove_witness, chunk_field_scripts, commit_public_key, u31_add, u31_mul,
+        FieldScript, U31Config, M31,
+    };
+    use bitvm::treepp::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    // x -> x * (x * c)
+    fn step_scripts(c: u32) -> Vec<FieldScript> {
+        vec![
+            FieldScript::new(script! { OP_DUP }, 1, 2),
+            FieldScript::u31_mul_by_constant::<M31>(c),
+            FieldScript::u31_mul::<M31>(),
+        ]
+    }
+
+    fn step_native(x: u32, c: u32) -> u32 {
+        let p = M31::MOD as u64;
+        ((x as u64 * (x as u64 * c as u64 % p)) % p) as u32
+    }
+
+    #[test]
+    fn test_chunker() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        let max_chunk_size = 5000;
+
+        let constants: Vec<u32> = (0..12).map(|_| prng.gen_range(1..M31::MOD)).collect();
+        let steps: Vec<FieldScript> = constants.iter().flat_map(|c| step_scripts(*c)).collect();
+        let chunks = chunk_field_scripts(&steps, 1, max_chunk_size);
+        eprintln!("chunks: {}", chunks.len());
+
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.last().unwrap().steps.end, steps.len());
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert!(chunk.script.len() <= max_chunk_size);
+            // the chunks only stop where the state is a single element
+            assert_eq!((chunk.inputs, chunk.outputs), (1, 1));
+            assert_eq!(chunk.steps.start % 3, 0);
+            if i > 0 {
+                assert_eq!(chunk.steps.start, chunks[i - 1].steps.end);
+            }
+        }
+
+        // the honest intermediate state of each chunk cannot be disproved, a wrong one can
+        let mut x = prng.gen_range(0..M31::MOD);
+        for (i, chunk) in chunks.iter().enumerate() {
+            let y = constants[chunk.steps.start / 3..chunk.steps.end / 3]
+                .iter()
+                .fold(x, |acc, c| step_native(acc, *c));
+
+            let input_secret_keys = vec![format!("{:040x}", i)];
+            let output_secret_keys = vec![format!("{:040x}", i + 1)];
+            let input_public_keys = vec![commit_public_key(&input_secret_keys[0])];
+            let output_public_keys = vec![commit_public_key(&output_secret_keys[0])];
+            let disprove = chunk.disprove_script::<M31>(&input_public_keys, &output_public_keys);
+
+            // the signatures are valid and the outputs match, so the script leaves false
+            let script = script! {
+                { chunk_disprove_witness(&input_secret_keys, &[x], &output_secret_keys, &[y]) }
+                { disprove.clone() }
+                OP_NOT
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            let script = script! {
+                {
+                    chunk_disprove_witness(
+                        &input_secret_keys,
+                        &[x],
+                        &output_secret_keys,
+                        &[(y + 1) % M31::MOD],
+                    )
+                }
+                { disprove.clone() }
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            x = y;
+        }
+    }
+
+    #[test]
+    fn test_chunk_disprove_non_canonical() {
+        let mut prng = ChaCha20Rng::seed_from_u64(2u64);
+        let c = prng.gen_range(1..M31::MOD);
+        let chunks = chunk_field_scripts(&step_scripts(c), 1, 10000);
+        assert_eq!(chunks.len(), 1);
+        let chunk = &chunks[0];
+
+        let input_secret_keys = vec![format!("{:040x}", 0)];
+        let output_secret_keys = vec![format!("{:040x}", 1)];
+        let input_public_keys = vec![commit_public_key(&input_secret_keys[0])];
+        let output_public_keys = vec![commit_public_key(&output_secret_keys[0])];
+        let disprove = chunk.disprove_script::<M31>(&input_public_keys, &output_public_keys);
+
+        let x = prng.gen_range(0..M31::MOD);
+        let y = step_native(x, c);
+
+        // MOD and a value with the top bit of b3 set, committed as the output or as the input,
+        // which runs as 0
+        for value in [M31::MOD, 0x8000_0000 + x] {
+            for (inputs, outputs) in [([x], [value]), ([value], [step_native(0, c)])] {
+                let script = script! {
+                    {
+                        chunk_disprove_witness(
+                            &input_secret_keys,
+                            &inputs,
+                            &output_secret_keys,
+                            &outputs,
+                        )
+                    }
+                    { disprove.clone() }
+                };
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+        }
+
+        // the honest commitments still cannot be disproved
+        let script = script! {
+            { chunk_disprove_witness(&input_secret_keys, &[x], &output_secret_keys, &[y]) }
+            { disprove.clone() }
+            OP_NOT
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    fn test_chunk_disprove_ordering() {
+        let mut prng = ChaCha20Rng::seed_from_u64(1u64);
+
+        // a, b -> a * b, a + b
+        let step = FieldScript::new(
+            script! {
+                OP_2DUP
+                { u31_mul::<M31>() }
+                OP_ROT OP_ROT
+                { u31_add::<M31>() }
+            },
+            2,
+            2,
+        );
+        let chunks = chunk_field_scripts(&[step], 2, 10000);
+        assert_eq!(chunks.len(), 1);
+        let chunk = &chunks[0];
+        assert_eq!((chunk.inputs, chunk.outputs), (2, 2));
+
+        let a = prng.gen_range(0..M31::MOD);
+        let b = prng.gen_range(0..M31::MOD);
+        let p = M31::MOD as u64;
+        let outputs = [
+            (a as u64 * b as u64 % p) as u32,
+            ((a as u64 + b as u64) % p) as u32,
+        ];
+
+        let input_secret_keys: Vec<String> = (0..2).map(|i| format!("{:040x}", i)).collect();
+        let output_secret_keys: Vec<String> = (2..4).map(|i| format!("{:040x}", i)).collect();
+        let input_public_keys: Vec<_> = input_secret_keys
+            .iter()
+            .map(|sk| commit_public_key(sk))
+            .collect();
+        let output_public_keys: Vec<_> = output_secret_keys
+            .iter()
+            .map(|sk| commit_public_key(sk))
+            .collect();
+        let disprove = chunk.disprove_script::<M31>(&input_public_keys, &output_public_keys);
+
+        // honest: every signature is checked against its own key and the outputs match
+        let script = script! {
+            { chunk_disprove_witness(&input_secret_keys, &[a, b], &output_secret_keys, &outputs) }
+            { disprove.clone() }
+            OP_NOT
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+
+        // each wrong output is disproved
+        for i in 0..2 {
+            let mut wrong = outputs;
+            wrong[i] = (wrong[i] + 1) % M31::MOD;
+            let script = script! {
+                { chunk_disprove_witness(&input_secret_keys, &[a, b], &output_secret_keys, &wrong) }
+                { disprove.clone() }
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        // swapping the keys of two elements breaks the signatures
+        let swapped_keys = vec![output_secret_keys[1].clone(), output_secret_keys[0].clone()];
+        let script = script! {
+            { chunk_disprove_witness(&input_secret_keys, &[a, b], &swapped_keys, &outputs) }
+            { disprove.clone() }
+            OP_DROP
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{karatsuba_complex_small, u31_add, u31_double, u31_mul, u31_sub, M31};
+use bitvm::treepp::*;
+
+// Input: a (qm31) = a0 + a1 * u
+// Output: a0 - a1 * u
+pub fn qm31_complex_conjugate() -> Script {
+    script! {
+        OP_2SWAP
+        0 OP_SWAP { u31_sub::<M31>() }
+        OP_SWAP
+        0 OP_SWAP { u31_sub::<M31>() }
+        OP_SWAP
+        OP_2SWAP
+    }
+}
+
+// Input:
+//      z.x (qm31) = c + d * u
+//      z.y (qm31) = a + b * u
+//      p.x (m31)
+//      p.y (m31)
+// Output:
+//      the line through z and conj(z) evaluated at p, as in Stwo's pair_vanishing:
+//      (z.y - conj(z.y)) * p.x + (conj(z.x) - z.x) * p.y + (z.x * conj(z.y) - z.y * conj(z.x))
+//
+// Since the imaginary parts cancel out, this equals 2u * (b * p.x - d * p.y + d * a - c * b),
+// which only needs two CM31 multiplications and four M31 multiplications.
+pub fn qm31_pair_vanishing() -> Script {
+    script! {
+        // d * a
+        9 OP_PICK 9 OP_PICK 5 OP_PICK 5 OP_PICK
+        { karatsuba_complex_small::<M31>() }
+        OP_TOALTSTACK OP_TOALTSTACK
+
+        // c * b
+        7 OP_PICK 7 OP_PICK 7 OP_PICK 7 OP_PICK
+        { karatsuba_complex_small::<M31>() }
+
+        // d * a - c * b
+        OP_FROMALTSTACK OP_FROMALTSTACK
+        OP_ROT { u31_sub::<M31>() }
+        OP_TOALTSTACK
+        OP_SWAP { u31_sub::<M31>() }
+        OP_FROMALTSTACK
+
+        // + b * p.x
+        6 OP_PICK 4 OP_PICK { u31_mul::<M31>() } { u31_add::<M31>() }
+        OP_SWAP
+        7 OP_PICK 4 OP_PICK { u31_mul::<M31>() } { u31_add::<M31>() }
+
+        // - d * p.y
+        11 OP_PICK 3 OP_PICK { u31_mul::<M31>() } { u31_sub::<M31>() }
+        OP_SWAP
+        10 OP_PICK 3 OP_PICK { u31_mul::<M31>() } { u31_sub::<M31>() }
+
+        // * 2
+        { u31_double::<M31>() }
+        OP_SWAP
+        { u31_double::<M31>() }
+        OP_SWAP
+
+        OP_TOALTSTACK OP_TOALTSTACK
+        OP_2DROP OP_2DROP OP_2DROP OP_2DROP OP_2DROP
+        OP_FROMALTSTACK OP_FROMALTSTACK
+
+        // * u
+        0 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{qm31_complex_conjugate, qm31_pair_vanishing, u31ext_equalverify, QM31};
+    use bitvm::treepp::*;
+    use core::ops::Neg;
+    use p3_field::extension::Complex;
+    use p3_field::{AbstractExtensionField, AbstractField, PrimeField32};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    type F = p3_field::extension::BinomialExtensionField<Complex<p3_mersenne_31::Mersenne31>, 2>;
+
+    fn complex_conjugate(a: F) -> F {
+        let a: &[Complex<p3_mersenne_31::Mersenne31>] = a.as_base_slice();
+        F::from_base_slice(&[a[0], a[1].neg()])
+    }
+
+    fn push_qm31(a: F) -> Script {
+        let a: &[Complex<p3_mersenne_31::Mersenne31>] = a.as_base_slice();
+        script! {
+            { a[1].imag().as_canonical_u32() }
+            { a[1].real().as_canonical_u32() }
+            { a[0].imag().as_canonical_u32() }
+            { a[0].real().as_canonical_u32() }
+        }
+    }
+
+    #[test]
+    fn test_qm31_complex_conjugate() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0u64);
+
+        for _ in 0..10 {
+            let a = rng.gen::<F>();
+
+            let script = script! {
+                { push_qm31(a) }
+                { qm31_complex_conjugate() }
+                { push_qm31(complex_conjugate(a)) }
+                { u31ext_equalverify::<QM31>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_qm31_pair_vanishing() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!("qm31 pair vanishing: {}", qm31_pair_vanishing().len());
+
+        for _ in 0..10 {
+            let zx = rng.gen::<F>();
+            let zy = rng.gen::<F>();
+            let px = rng.gen::<p3_mersenne_31::Mersenne31>();
+            let py = rng.gen::<p3_mersenne_31::Mersenne31>();
+
+            let zero = p3_mersenne_31::Mersenne31::zero();
+            let px_ext = F::from_base(Complex::new(px, zero));
+            let py_ext = F::from_base(Complex::new(py, zero));
+            let conj_zx = complex_conjugate(zx);
+            let conj_zy = complex_conjugate(zy);
+
+            let expected =
+                (zy - conj_zy) * px_ext + (conj_zx - zx) * py_ext + (zx * conj_zy - zy * conj_zx);
+
+            let script = script! {
+                { push_qm31(zx) }
+                { push_qm31(zy) }
+                { px.as_canonical_u32() }
+                { py.as_canonical_u32() }
+                { qm31_pair_vanishing() }
+                { push_qm31(expected) }
+                { u31ext_equalverify::<QM31>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+}
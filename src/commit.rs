@@ -0,0 +1,361 @@
+use crate::{u31_to_nibbles_native, u31ext_to_nibbles_native, U31Config, U31ExtConfig};
+use bitvm::signatures::winternitz::{checksig_verify, generate_public_key, sign_digits, PublicKey};
+use bitvm::treepp::*;
+
+// Commitments to field elements with the Winternitz signatures of BitVM
+// (`bitvm::signatures::winternitz`), which sign a 20-byte message given as 40 nibbles, the low
+// nibble of each byte first, and check the checksum of the nibbles themselves. An element is
+// signed as its nibbles (see u31_to_nibbles_native), the limbs of an extension element one
+// after the other from limb 0 on, and the rest of the message is zero.
+const MESSAGE_BYTES: usize = 20;
+
+// The number of elements of a signature, a hash and a digit for each of the 40 message digits
+// and the 4 checksum digits
+pub const COMMIT_SIGNATURE_LEN: usize = 2 * 44;
+
+fn commit_sign(secret_key: &str, nibbles: &[u8]) -> Script {
+    let mut digits = [0u8; 2 * MESSAGE_BYTES];
+    digits[..nibbles.len()].copy_from_slice(nibbles);
+    sign_digits(secret_key, digits)
+}
+
+// The public key for both u31 and u31ext commitments, the secret key being given in hex
+pub fn commit_public_key(secret_key: &str) -> PublicKey {
+    generate_public_key(secret_key)
+}
+
+// The witness for u31_commit_and_verify
+pub fn u31_commit_sign(secret_key: &str, a: u32) -> Script {
+    commit_sign(secret_key, &u31_to_nibbles_native(a))
+}
+
+// The witness for u31ext_commit_and_verify, with a given limb 0 first
+pub fn u31ext_commit_sign(secret_key: &str, a: &[u32]) -> Script {
+    commit_sign(secret_key, &u31ext_to_nibbles_native(a))
+}
+
+// Input: the signature (see u31_commit_sign)
+// Output: a, the signed element, which is checked to be smaller than MOD
+pub fn u31_commit_and_verify<M: U31Config>(public_key: &PublicKey) -> Script {
+    commit_and_verify::<M>(public_key, 1)
+}
+
+// Input: the signature (see u31ext_commit_sign)
+// Output: a (u31ext), the signed element, with each limb checked to be smaller than MOD
+pub fn u31ext_commit_and_verify<C: U31ExtConfig>(public_key: &PublicKey) -> Script {
+    commit_and_verify::<C::BaseFieldConfig>(public_key, C::DEGREE as usize)
+}
+
+// Input: the signature (see u31_commit_sign)
+// Output: a, invalid
+//
+// Only the signature is verified. invalid is 1 if a is not smaller than MOD, in which case a is
+// replaced with 0, or if the message has anything besides a, so that a disprove script can
+// treat such a commitment as disproved instead of failing.
+pub fn u31_commit_and_check<M: U31Config>(public_key: &PublicKey) -> Script {
+    commit_and_check::<M>(public_key, 1)
+}
+
+// Input: the signature (see u31ext_commit_sign)
+// Output: a (u31ext), invalid
+//
+// Same as u31_commit_and_check, with the limbs that are not canonical replaced with 0.
+pub fn u31ext_commit_and_check<C: U31ExtConfig>(public_key: &PublicKey) -> Script {
+    commit_and_check::<C::BaseFieldConfig>(public_key, C::DEGREE as usize)
+}
+
+fn commit_and_verify<M: U31Config>(public_key: &PublicKey, num_limbs: usize) -> Script {
+    assert!(4 * num_limbs <= MESSAGE_BYTES);
+
+    script! {
+        // leaves the message bytes, the first one on top
+        { checksig_verify(public_key) }
+        for _ in 0..num_limbs {
+            { u31_from_message_bytes::<M>() }
+            OP_TOALTSTACK
+        }
+        for _ in 0..MESSAGE_BYTES - 4 * num_limbs {
+            OP_NOT OP_VERIFY
+        }
+        for _ in 0..num_limbs {
+            OP_FROMALTSTACK
+        }
+    }
+}
+
+fn commit_and_check<M: U31Config>(public_key: &PublicKey, num_limbs: usize) -> Script {
+    assert!(4 * num_limbs <= MESSAGE_BYTES);
+    // whether any byte after the element is nonzero
+    let padding = MESSAGE_BYTES - 4 * num_limbs;
+    let padding_nonzero = if padding > 0 {
+        script! {
+            OP_0NOTEQUAL
+            for _ in 1..padding {
+                OP_SWAP OP_0NOTEQUAL OP_BOOLOR
+            }
+        }
+    } else {
+        script! { 0 }
+    };
+
+    script! {
+        // leaves the message bytes, the first one on top
+        { checksig_verify(public_key) }
+        for _ in 0..num_limbs {
+            { u31_from_message_bytes_checked::<M>() }
+            OP_TOALTSTACK OP_TOALTSTACK
+        }
+
+        { padding_nonzero }
+
+        for _ in 0..num_limbs {
+            OP_FROMALTSTACK OP_FROMALTSTACK
+            OP_ROT OP_BOOLOR
+        }
+    }
+}
+
+// Input: b3, b2, b1, b0, the little-endian bytes of a
+// Output: a, which is checked to be smaller than MOD
+//
+// A b3 of 128 or more makes an intermediate value overflow the 4-byte script numbers, which
+// fails the script as well.
+fn u31_from_message_bytes<M: U31Config>() -> Script {
+    script! {
+        3 OP_ROLL
+        for depth in [3, 2] {
+            for _ in 0..8 {
+                OP_DUP OP_ADD
+            }
+            { depth } OP_ROLL OP_ADD
+        }
+        for _ in 0..8 {
+            OP_DUP OP_ADD
+        }
+        OP_ADD
+        OP_DUP { M::MOD } OP_LESSTHAN OP_VERIFY
+    }
+}
+
+// Input: b3, b2, b1, b0, the little-endian bytes of a
+// Output: a, 0 if a is smaller than MOD, and 0, 1 otherwise
+//
+// The top bit of b3 is taken out before the recombination, so that it cannot overflow.
+fn u31_from_message_bytes_checked<M: U31Config>() -> Script {
+    script! {
+        3 OP_ROLL
+        OP_DUP 128 OP_GREATERTHANOREQUAL
+        OP_DUP OP_TOALTSTACK
+        OP_IF 128 OP_SUB OP_ENDIF
+        for depth in [3, 2] {
+            for _ in 0..8 {
+                OP_DUP OP_ADD
+            }
+            { depth } OP_ROLL OP_ADD
+        }
+        for _ in 0..8 {
+            OP_DUP OP_ADD
+        }
+        OP_ADD
+        OP_DUP { M::MOD } OP_GREATERTHANOREQUAL
+        OP_FROMALTSTACK OP_BOOLOR
+        OP_DUP OP_IF OP_NIP 0 OP_SWAP OP_ENDIF
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        commit_public_key, u31_add, u31_commit_and_check, u31_commit_and_verify, u31_commit_sign,
+        u31_to_nibbles_native, u31ext_commit_and_check, u31ext_commit_and_verify,
+        u31ext_commit_sign, u31ext_equalverify, BabyBear, BabyBear4, U31Config, U31ExtConfig, M31,
+        QM31,
+    };
+    use bitvm::signatures::winternitz::sign_digits;
+    use bitvm::treepp::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    const SECRET_KEY: &str = "b138982ce17ac813d505b5b40b665d404e9528e7";
+    const OTHER_SECRET_KEY: &str = "a138982ce17ac813d505b5b40b665d404e9528e7";
+
+    fn test_u31_commit_and_verify_generic<M: U31Config>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+        let public_key = commit_public_key(SECRET_KEY);
+
+        for _ in 0..10 {
+            let a = prng.gen_range(0..M::MOD);
+            let b = prng.gen_range(0..M::MOD);
+            let c = (a + b) % M::MOD;
+
+            let script = script! {
+                { u31_commit_sign(SECRET_KEY, a) }
+                { u31_commit_and_verify::<M>(&public_key) }
+                { b }
+                { u31_add::<M>() }
+                { c }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        // a non-canonical element is rejected even with a valid signature
+        for a in [M::MOD, u32::MAX] {
+            let script = script! {
+                { u31_commit_sign(SECRET_KEY, a) }
+                { u31_commit_and_verify::<M>(&public_key) }
+                OP_DROP
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(!exec_result.success);
+        }
+
+        // so is a message with anything besides a
+        let mut digits = [0u8; 40];
+        digits[..8].copy_from_slice(&u31_to_nibbles_native(1));
+        digits[39] = 1;
+        let script = script! {
+            { sign_digits(SECRET_KEY, digits) }
+            { u31_commit_and_verify::<M>(&public_key) }
+            OP_DROP
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+
+        // and a signature under another key
+        let script = script! {
+            { u31_commit_sign(OTHER_SECRET_KEY, 1) }
+            { u31_commit_and_verify::<M>(&public_key) }
+            OP_DROP
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+
+    #[test]
+    fn test_u31_commit_and_verify() {
+        let public_key = commit_public_key(SECRET_KEY);
+        eprintln!(
+            "u31 commit and verify: {}",
+            u31_commit_and_verify::<M31>(&public_key).len()
+        );
+
+        test_u31_commit_and_verify_generic::<M31>(0u64);
+        test_u31_commit_and_verify_generic::<BabyBear>(1u64);
+    }
+
+    fn test_u31ext_commit_and_verify_generic<C: U31ExtConfig>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+        let public_key = commit_public_key(SECRET_KEY);
+        let modulus = <C::BaseFieldConfig as U31Config>::MOD;
+
+        for _ in 0..10 {
+            let a: Vec<u32> = (0..C::DEGREE).map(|_| prng.gen_range(0..modulus)).collect();
+
+            let script = script! {
+                { u31ext_commit_sign(SECRET_KEY, &a) }
+                { u31ext_commit_and_verify::<C>(&public_key) }
+                for x in a.iter().rev() {
+                    { *x }
+                }
+                { u31ext_equalverify::<C>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        let mut a = vec![0u32; C::DEGREE as usize];
+        a[C::DEGREE as usize - 1] = modulus;
+        let script = script! {
+            { u31ext_commit_sign(SECRET_KEY, &a) }
+            { u31ext_commit_and_verify::<C>(&public_key) }
+            for _ in 0..C::DEGREE {
+                OP_DROP
+            }
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+
+    fn test_u31_commit_and_check_generic<M: U31Config>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+        let public_key = commit_public_key(SECRET_KEY);
+
+        let a = prng.gen_range(0..M::MOD);
+        let mut extra = [0u8; 40];
+        extra[..8].copy_from_slice(&u31_to_nibbles_native(a));
+        extra[39] = 1;
+
+        // the signature, a, and invalid
+        for (signature, expected, invalid) in [
+            (u31_commit_sign(SECRET_KEY, a), a, 0),
+            (u31_commit_sign(SECRET_KEY, M::MOD), 0, 1),
+            (u31_commit_sign(SECRET_KEY, 0x8000_0000 + a), 0, 1),
+            (u31_commit_sign(SECRET_KEY, u32::MAX), 0, 1),
+            (sign_digits(SECRET_KEY, extra), a, 1),
+        ] {
+            let script = script! {
+                { signature }
+                { u31_commit_and_check::<M>(&public_key) }
+                { invalid } OP_EQUALVERIFY
+                { expected } OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        // the signature is still verified
+        let script = script! {
+            { u31_commit_sign(OTHER_SECRET_KEY, a) }
+            { u31_commit_and_check::<M>(&public_key) }
+            OP_2DROP
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+
+    #[test]
+    fn test_u31_commit_and_check() {
+        test_u31_commit_and_check_generic::<M31>(2u64);
+        test_u31_commit_and_check_generic::<BabyBear>(3u64);
+    }
+
+    #[test]
+    fn test_u31ext_commit_and_check() {
+        let public_key = commit_public_key(SECRET_KEY);
+        let modulus = M31::MOD;
+
+        // a limb of MOD is replaced with 0 and the others are kept
+        let a = [1, modulus, 2, 3];
+        let script = script! {
+            { u31ext_commit_sign(SECRET_KEY, &a) }
+            { u31ext_commit_and_check::<QM31>(&public_key) }
+            1 OP_EQUALVERIFY
+            1 OP_EQUALVERIFY
+            0 OP_EQUALVERIFY
+            2 OP_EQUALVERIFY
+            3 OP_EQUAL
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    fn test_u31ext_commit_and_verify() {
+        let public_key = commit_public_key(SECRET_KEY);
+        eprintln!(
+            "qm31 commit and verify: {}",
+            u31ext_commit_and_verify::<QM31>(&public_key).len()
+        );
+
+        test_u31ext_commit_and_verify_generic::<QM31>(0u64);
+        test_u31ext_commit_and_verify_generic::<BabyBear4>(1u64);
+    }
+}
@@ -0,0 +1,403 @@
+use crate::{
+    commit_public_key, deep_quotient, deep_quotient_checked, karatsuba_big, karatsuba_complex_big,
+    karatsuba_complex_small, karatsuba_small, lagrange_first_row, lagrange_last_row,
+    merkle_verify_path, poseidon2_external_layer, poseidon2_internal_layer, poseidon2_permute,
+    poseidon2_sbox, qm31_complex_conjugate, qm31_pair_vanishing, stack_usage, u31_add,
+    u31_batch_inv, u31_batch_inv_hinted, u31_commit_and_check, u31_commit_and_verify, u31_double,
+    u31_from_bits, u31_from_nibbles, u31_from_u32_limbs, u31_mul, u31_mul_by_constant,
+    u31_mul_hinted, u31_mul_many, u31_mul_windowed, u31_neg, u31_sub, u31_to_bits, u31_to_le_bytes,
+    u31_to_nibbles, u31_to_u32_limbs, u31_vec_hash, u31ext_add, u31ext_batch_inv,
+    u31ext_batch_inv_hinted, u31ext_commit_and_check, u31ext_commit_and_verify, u31ext_double,
+    u31ext_hash, u31ext_many_mul_u31, u31ext_mul, u31ext_mul_u31, u31ext_mul_u31_by_constant,
+    u31ext_sub, u31ext_to_nibbles, u32_reduce, u64_reduce, vanishing_eval, BabyBear, BabyBear4,
+    Poseidon2BabyBear, Poseidon2Config, Poseidon2M31, U31Config, U31ExtConfig,
+    COMMIT_SIGNATURE_LEN, M31, POSEIDON2_WIDTH, QM31,
+};
+use bitvm::treepp::*;
+
+// The constant used for the multiplications by a constant, whose cost depends on it
+const COST_CONSTANT: u32 = 1234567;
+
+// The key of the commitments, whose size does not depend on it
+const COST_SECRET_KEY: &str = "b138982ce17ac813d505b5b40b665d404e9528e7";
+
+pub struct PrimitiveCost {
+    pub name: &'static str,
+    pub field: &'static str,
+    // in a tapscript leaf, each byte of the script is one weight unit
+    pub size: usize,
+    // the peak number of elements on the stack and the altstack together, inputs included, or
+    // None if stack_usage cannot bound it
+    pub max_stack: Option<usize>,
+}
+
+impl PrimitiveCost {
+    fn new(name: &'static str, field: &'static str, script: Script, inputs: usize) -> Self {
+        Self {
+            name,
+            field,
+            size: script.len(),
+            max_stack: stack_usage(&script).map(|usage| inputs + usage.max_growth),
+        }
+    }
+}
+
+pub fn primitive_costs() -> Vec<PrimitiveCost> {
+    let mut costs = vec![];
+    costs.extend(u31_costs::<M31>("M31"));
+    costs.extend(u31_costs::<BabyBear>("BabyBear"));
+    costs.extend(u31ext_costs::<QM31>("QM31"));
+    costs.extend(u31ext_costs::<BabyBear4>("BabyBear4"));
+    costs.extend(circle_costs());
+    costs.push(PrimitiveCost::new(
+        "vanishing_eval_10",
+        "QM31",
+        // the circle subgroup itself
+        vanishing_eval::<QM31>(10, (1, 0)),
+        8,
+    ));
+    costs.push(PrimitiveCost::new(
+        "vanishing_eval_10",
+        "BabyBear4",
+        vanishing_eval::<BabyBear4>(10, 31),
+        4,
+    ));
+    // the hinted inverse, then the point
+    costs.push(PrimitiveCost::new(
+        "lagrange_first_row_10",
+        "QM31",
+        lagrange_first_row::<QM31>(10, (1, 0)),
+        12,
+    ));
+    costs.push(PrimitiveCost::new(
+        "lagrange_last_row_10",
+        "QM31",
+        lagrange_last_row::<QM31>(10, (1, 0)),
+        12,
+    ));
+    costs.push(PrimitiveCost::new(
+        "lagrange_first_row_10",
+        "BabyBear4",
+        lagrange_first_row::<BabyBear4>(10, 31),
+        8,
+    ));
+    costs.push(PrimitiveCost::new(
+        "lagrange_last_row_10",
+        "BabyBear4",
+        lagrange_last_row::<BabyBear4>(10, 31),
+        8,
+    ));
+    costs.extend(poseidon2_costs::<Poseidon2M31>("M31"));
+    costs.extend(poseidon2_costs::<Poseidon2BabyBear>("BabyBear"));
+    costs
+}
+
+fn u31_costs<M: U31Config>(field: &'static str) -> Vec<PrimitiveCost> {
+    let mut costs = vec![
+        PrimitiveCost::new("u31_add", field, u31_add::<M>(), 2),
+        PrimitiveCost::new("u31_sub", field, u31_sub::<M>(), 2),
+        PrimitiveCost::new("u31_double", field, u31_double::<M>(), 1),
+        PrimitiveCost::new("u31_neg", field, u31_neg::<M>(), 1),
+        PrimitiveCost::new("u31_mul", field, u31_mul::<M>(), 2),
+        PrimitiveCost::new(
+            "u31_mul_by_constant",
+            field,
+            u31_mul_by_constant::<M>(COST_CONSTANT),
+            1,
+        ),
+        PrimitiveCost::new("u31_mul_hinted", field, u31_mul_hinted::<M>(), 23),
+        PrimitiveCost::new("u31_to_bits", field, u31_to_bits(), 1),
+        PrimitiveCost::new("u31_from_bits", field, u31_from_bits(31), 31),
+        PrimitiveCost::new("u31_to_nibbles", field, u31_to_nibbles(), 1),
+        PrimitiveCost::new("u31_from_nibbles", field, u31_from_nibbles(), 8),
+        PrimitiveCost::new("u31_to_u32_limbs", field, u31_to_u32_limbs(), 1),
+        PrimitiveCost::new("u31_from_u32_limbs", field, u31_from_u32_limbs::<M>(), 4),
+        PrimitiveCost::new("u32_reduce", field, u32_reduce::<M>(), 2),
+        PrimitiveCost::new("u64_reduce", field, u64_reduce::<M>(), 4),
+        PrimitiveCost::new("u31_to_le_bytes", field, u31_to_le_bytes::<M>(), 1),
+        PrimitiveCost::new("u31_vec_hash_8", field, u31_vec_hash::<M>(8), 8),
+        PrimitiveCost::new("u31_mul_many_8", field, u31_mul_many::<M>(8), 9),
+        PrimitiveCost::new("karatsuba_small", field, karatsuba_small::<M>(), 4),
+        PrimitiveCost::new("karatsuba_big", field, karatsuba_big::<M>(), 8),
+    ];
+    let windowed = [
+        "u31_mul_windowed_1",
+        "u31_mul_windowed_3",
+        "u31_mul_windowed_4",
+    ];
+    for (name, w) in windowed.into_iter().zip([1, 3, 4]) {
+        costs.push(PrimitiveCost::new(name, field, u31_mul_windowed::<M>(w), 2));
+    }
+    costs.push(PrimitiveCost::new(
+        "u31_batch_inv_8",
+        field,
+        u31_batch_inv::<M>(8),
+        9,
+    ));
+    costs.push(PrimitiveCost::new(
+        "u31_batch_inv_hinted_8",
+        field,
+        u31_batch_inv_hinted::<M>(8),
+        16,
+    ));
+
+    let public_key = commit_public_key(COST_SECRET_KEY);
+    costs.push(PrimitiveCost::new(
+        "u31_commit_and_verify",
+        field,
+        u31_commit_and_verify::<M>(&public_key),
+        COMMIT_SIGNATURE_LEN,
+    ));
+    costs.push(PrimitiveCost::new(
+        "u31_commit_and_check",
+        field,
+        u31_commit_and_check::<M>(&public_key),
+        COMMIT_SIGNATURE_LEN,
+    ));
+    costs
+}
+
+fn u31ext_costs<C: U31ExtConfig>(field: &'static str) -> Vec<PrimitiveCost> {
+    let degree = C::DEGREE as usize;
+    let public_key = commit_public_key(COST_SECRET_KEY);
+    vec![
+        PrimitiveCost::new("u31ext_add", field, u31ext_add::<C>(), 2 * degree),
+        PrimitiveCost::new("u31ext_sub", field, u31ext_sub::<C>(), 2 * degree),
+        PrimitiveCost::new("u31ext_double", field, u31ext_double::<C>(), degree),
+        PrimitiveCost::new("u31ext_mul", field, u31ext_mul::<C>(), 2 * degree),
+        PrimitiveCost::new("u31ext_mul_u31", field, u31ext_mul_u31::<C>(), degree + 1),
+        PrimitiveCost::new(
+            "u31ext_mul_u31_by_constant",
+            field,
+            u31ext_mul_u31_by_constant::<C>(COST_CONSTANT),
+            degree,
+        ),
+        PrimitiveCost::new(
+            "u31ext_many_mul_u31_4",
+            field,
+            u31ext_many_mul_u31::<C>(4),
+            4 * degree + 1,
+        ),
+        PrimitiveCost::new("u31ext_hash", field, u31ext_hash::<C>(), degree),
+        PrimitiveCost::new("u31ext_to_nibbles", field, u31ext_to_nibbles::<C>(), degree),
+        PrimitiveCost::new(
+            "u31ext_batch_inv_8",
+            field,
+            u31ext_batch_inv::<C>(8),
+            9 * degree,
+        ),
+        PrimitiveCost::new(
+            "u31ext_batch_inv_hinted_8",
+            field,
+            u31ext_batch_inv_hinted::<C>(8),
+            16 * degree,
+        ),
+        PrimitiveCost::new(
+            "deep_quotient_8",
+            field,
+            deep_quotient::<C>(8),
+            3 * degree + 1 + 8 * (degree + 1),
+        ),
+        PrimitiveCost::new(
+            "deep_quotient_checked_8",
+            field,
+            deep_quotient_checked::<C>(8),
+            11 * degree + 9,
+        ),
+        // the root, the siblings and their bits, and the leaf
+        PrimitiveCost::new(
+            "merkle_verify_path_20",
+            field,
+            merkle_verify_path::<C>(20),
+            1 + 2 * 20 + degree,
+        ),
+        PrimitiveCost::new(
+            "u31ext_commit_and_verify",
+            field,
+            u31ext_commit_and_verify::<C>(&public_key),
+            COMMIT_SIGNATURE_LEN,
+        ),
+        PrimitiveCost::new(
+            "u31ext_commit_and_check",
+            field,
+            u31ext_commit_and_check::<C>(&public_key),
+            COMMIT_SIGNATURE_LEN,
+        ),
+    ]
+}
+
+// The complex arithmetic of QM31 over CM31, with M31 limbs
+fn circle_costs() -> Vec<PrimitiveCost> {
+    vec![
+        PrimitiveCost::new(
+            "karatsuba_complex_small",
+            "M31",
+            karatsuba_complex_small::<M31>(),
+            4,
+        ),
+        PrimitiveCost::new(
+            "karatsuba_complex_big",
+            "M31",
+            karatsuba_complex_big::<M31>(),
+            8,
+        ),
+        PrimitiveCost::new(
+            "qm31_complex_conjugate",
+            "QM31",
+            qm31_complex_conjugate(),
+            4,
+        ),
+        PrimitiveCost::new("qm31_pair_vanishing", "QM31", qm31_pair_vanishing(), 10),
+    ]
+}
+
+// The round constants are inputs of the permutation, whose size depends on them
+fn poseidon2_costs<C: Poseidon2Config>(field: &'static str) -> Vec<PrimitiveCost> {
+    let modulus = C::BaseFieldConfig::MOD as u64;
+    let constant = |i: usize| (COST_CONSTANT as u64 * (i as u64 + 1) % modulus) as u32;
+    let external_constants: Vec<[u32; POSEIDON2_WIDTH]> = (0..C::ROUNDS_F)
+        .map(|r| core::array::from_fn(|j| constant(r * POSEIDON2_WIDTH + j)))
+        .collect();
+    let internal_constants: Vec<u32> = (0..C::ROUNDS_P)
+        .map(|k| constant(C::ROUNDS_F * POSEIDON2_WIDTH + k))
+        .collect();
+
+    vec![
+        PrimitiveCost::new("poseidon2_sbox", field, poseidon2_sbox::<C>(), 1),
+        PrimitiveCost::new(
+            "poseidon2_external_layer",
+            field,
+            poseidon2_external_layer::<C>(),
+            POSEIDON2_WIDTH,
+        ),
+        PrimitiveCost::new(
+            "poseidon2_internal_layer",
+            field,
+            poseidon2_internal_layer::<C>(),
+            POSEIDON2_WIDTH,
+        ),
+        PrimitiveCost::new(
+            "poseidon2_permute",
+            field,
+            poseidon2_permute::<C>(&external_constants, &internal_constants),
+            POSEIDON2_WIDTH,
+        ),
+    ]
+}
+
+// An unknown peak stack usage is left empty
+pub fn costs_to_csv(costs: &[PrimitiveCost]) -> String {
+    let mut csv = String::from("name,field,size,max_stack\n");
+    for cost in costs.iter() {
+        let max_stack = cost.max_stack.map(|x| x.to_string()).unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            cost.name, cost.field, cost.size, max_stack
+        ));
+    }
+    csv
+}
+
+// An unknown peak stack usage is null
+pub fn costs_to_json(costs: &[PrimitiveCost]) -> String {
+    let entries: Vec<String> = costs
+        .iter()
+        .map(|cost| {
+            let max_stack = cost
+                .max_stack
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "null".to_string());
+            format!(
+                "  {{\"name\": \"{}\", \"field\": \"{}\", \"size\": {}, \"max_stack\": {}}}",
+                cost.name, cost.field, cost.size, max_stack
+            )
+        })
+        .collect();
+    format!("[\n{}\n]\n", entries.join(",\n"))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{costs_to_csv, costs_to_json, primitive_costs, MAX_STACK_ITEMS};
+
+    // name, field, size, max_stack
+    const BASELINE: &str = include_str!("costs_baseline.csv");
+
+    #[test]
+    fn test_costs_report() {
+        let costs = primitive_costs();
+        let csv = costs_to_csv(&costs);
+        eprint!("{}", csv);
+
+        assert_eq!(csv.lines().count(), costs.len() + 1);
+        assert!(costs_to_json(&costs).contains("\"name\": \"u31_mul\""));
+        for cost in costs.iter() {
+            if let Some(max_stack) = cost.max_stack {
+                assert!(
+                    max_stack <= MAX_STACK_ITEMS,
+                    "{} ({})",
+                    cost.name,
+                    cost.field
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_costs_regression() {
+        let costs = primitive_costs();
+
+        // every reported primitive has a baseline, and the other way round
+        let baseline_keys: Vec<(&str, &str)> = BASELINE
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split(',').collect();
+                (fields[0], fields[1])
+            })
+            .collect();
+        for cost in costs.iter() {
+            assert!(
+                baseline_keys.contains(&(cost.name, cost.field)),
+                "{} ({}) has no baseline",
+                cost.name,
+                cost.field
+            );
+        }
+        assert_eq!(baseline_keys.len(), costs.len(), "duplicate baseline rows");
+
+        for line in BASELINE.lines().skip(1) {
+            let fields: Vec<&str> = line.split(',').collect();
+            let (name, field) = (fields[0], fields[1]);
+            let baseline: usize = fields[2].parse().unwrap();
+            // empty if the peak stack usage is not known
+            let baseline_max_stack: Option<usize> = fields[3].parse().ok();
+
+            let cost = costs
+                .iter()
+                .find(|cost| cost.name == name && cost.field == field)
+                .unwrap_or_else(|| panic!("{} ({}) is not in the report", name, field));
+            assert!(
+                cost.size <= baseline,
+                "{} ({}) grew from {} to {} weight units",
+                name,
+                field,
+                baseline,
+                cost.size
+            );
+            if let Some(baseline_max_stack) = baseline_max_stack {
+                let max_stack = cost.max_stack.unwrap_or_else(|| {
+                    panic!("{} ({}) no longer has a known stack usage", name, field)
+                });
+                assert!(
+                    max_stack <= baseline_max_stack,
+                    "{} ({}) uses up to {} stack elements instead of {}",
+                    name,
+                    field,
+                    max_stack,
+                    baseline_max_stack
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use crate::U31ExtConfig;
+use crate::{
+    u31_sub, u31ext_add, u31ext_copy, u31ext_equalverify_one, u31ext_fromaltstack, u31ext_mul,
+    u31ext_mul_u31, u31ext_roll, u31ext_sub, u31ext_toaltstack,
+};
+use bitvm::treepp::*;
+
+// Input:
+//      (z - x)^{-1} (u31ext, supplied by the prover)
+//      z (u31ext)
+//      sum_i alpha^i * f_i(z) (u31ext)
+//      x (u31)
+//      alpha^0 (u31ext), f_0(x) (u31)
+//      ...
+//      alpha^{n - 1} (u31ext), f_{n - 1}(x) (u31)
+// Output:
+//      sum_i alpha^i * (f_i(x) - f_i(z)) / (x - z)
+//
+// The powers of alpha and the combination of the f_i(z) are the same for every query, so they
+// are computed once and each column only takes a u31ext_mul_u31, which decomposes f_i(x) into
+// bits once for all the limbs. The hinted inverse is checked with a single multiplication, so
+// the script fails if x = z.
+//
+// The powers of alpha and sum_i alpha^i * f_i(z) are NOT checked: like the inverse, they are
+// hints, and the caller must derive them from alpha and the f_i(z) elsewhere or use
+// deep_quotient_checked.
+pub fn deep_quotient<C: U31ExtConfig>(n_columns: usize) -> Script {
+    assert!(n_columns >= 1);
+
+    script! {
+        { u31ext_mul_u31::<C>() }
+        { u31ext_toaltstack::<C>() }
+
+        for _ in 1..n_columns {
+            { u31ext_mul_u31::<C>() }
+            { u31ext_fromaltstack::<C>() }
+            { u31ext_add::<C>() }
+            { u31ext_toaltstack::<C>() }
+        }
+
+        // z - x
+        OP_TOALTSTACK
+        { u31ext_roll::<C>(1) }
+        OP_FROMALTSTACK
+        { u31_sub::<C::BaseFieldConfig>() }
+
+        // check the hinted inverse
+        { u31ext_copy::<C>(2) }
+        { u31ext_mul::<C>() }
+        { u31ext_equalverify_one::<C>() }
+
+        // (sum_i alpha^i * f_i(z) - sum_i alpha^i * f_i(x)) / (z - x)
+        { u31ext_fromaltstack::<C>() }
+        { u31ext_sub::<C>() }
+        { u31ext_mul::<C>() }
+    }
+}
+
+// Input:
+//      (z - x)^{-1} (u31ext, supplied by the prover)
+//      z (u31ext)
+//      alpha (u31ext)
+//      f_0(z), ..., f_{n - 1}(z) (u31ext)
+//      x (u31)
+//      f_0(x), ..., f_{n - 1}(x) (u31)
+// Output:
+//      sum_i alpha^i * (f_i(x) - f_i(z)) / (x - z)
+//
+// Same as deep_quotient, with the powers of alpha and sum_i alpha^i * f_i(z) computed in the
+// script, which takes 2n - 3 more extension field multiplications (none for n = 1).
+pub fn deep_quotient_checked<C: U31ExtConfig>(n_columns: usize) -> Script {
+    assert!(n_columns >= 1);
+    let degree = C::DEGREE as usize;
+
+    // Input: ..., alpha, alpha^i (u31ext)
+    // Output: ..., alpha^i, f_i(x), alpha, alpha^{i + 1}
+    let next_power = script! {
+        { u31ext_copy::<C>(0) }
+        { u31ext_copy::<C>(2) }
+        { u31ext_mul::<C>() }
+        { u31ext_roll::<C>(1) }
+        OP_FROMALTSTACK
+        for _ in 0..2 * degree {
+            { 3 * degree } OP_ROLL
+        }
+    };
+
+    // Input: ..., alpha (u31ext)
+    // Output: ..., alpha^1, f_1(x), ..., alpha^{n - 1}, f_{n - 1}(x)
+    let powers = if n_columns == 1 {
+        script! {
+            for _ in 0..degree {
+                OP_DROP
+            }
+        }
+    } else {
+        script! {
+            { u31ext_copy::<C>(0) }
+            for _ in 2..n_columns {
+                { next_power.clone() }
+            }
+            { u31ext_roll::<C>(1) }
+            for _ in 0..degree {
+                OP_DROP
+            }
+            OP_FROMALTSTACK
+        }
+    };
+
+    script! {
+        for _ in 0..=n_columns {
+            OP_TOALTSTACK
+        }
+
+        // sum_i alpha^i * f_i(z) with Horner's rule
+        for k in 1..n_columns {
+            { u31ext_copy::<C>(n_columns - k + 1) }
+            { u31ext_mul::<C>() }
+            { u31ext_add::<C>() }
+        }
+
+        // x below alpha
+        { u31ext_roll::<C>(1) }
+        OP_FROMALTSTACK
+        for _ in 0..degree {
+            { degree } OP_ROLL
+        }
+
+        // alpha^0 = 1, f_0(x)
+        for _ in 1..degree {
+            0
+        }
+        1
+        OP_FROMALTSTACK
+        for _ in 0..degree {
+            { 2 * degree } OP_ROLL
+        }
+
+        { powers }
+        { deep_quotient::<C>(n_columns) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{deep_quotient, deep_quotient_checked, u31ext_equalverify, BabyBear4, QM31};
+    use bitvm::treepp::*;
+    use p3_field::extension::Complex;
+    use p3_field::{AbstractExtensionField, AbstractField, Field, PrimeField32};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use risc0_core::field::baby_bear::{BabyBearElem, BabyBearExtElem};
+    use risc0_core::field::{Elem, ExtElem};
+
+    type F = p3_field::extension::BinomialExtensionField<Complex<p3_mersenne_31::Mersenne31>, 2>;
+
+    #[test]
+    fn test_deep_quotient_babybear4() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!(
+            "babybear4 deep quotient (8 columns): {}",
+            deep_quotient::<BabyBear4>(8).len()
+        );
+        eprintln!(
+            "babybear4 deep quotient checked (8 columns): {}",
+            deep_quotient_checked::<BabyBear4>(8).len()
+        );
+
+        for n_columns in [1, 2, 8] {
+            let z = BabyBearExtElem::random(&mut prng);
+            let alpha = BabyBearExtElem::random(&mut prng);
+            let x = BabyBearElem::random(&mut prng);
+
+            let fz: Vec<BabyBearExtElem> = (0..n_columns)
+                .map(|_| BabyBearExtElem::random(&mut prng))
+                .collect();
+            let fx: Vec<BabyBearElem> = (0..n_columns)
+                .map(|_| BabyBearElem::random(&mut prng))
+                .collect();
+
+            let x_ext = BabyBearExtElem::from_subfield(&x);
+            let hint = (z - x_ext).inv();
+
+            let mut alpha_pows = vec![];
+            let mut alpha_pow = BabyBearExtElem::ONE;
+            for _ in 0..n_columns {
+                alpha_pows.push(alpha_pow);
+                alpha_pow = alpha_pow * alpha;
+            }
+
+            let mut numerator = BabyBearExtElem::ZERO;
+            let mut sum_z = BabyBearExtElem::ZERO;
+            for ((fz, fx), alpha_pow) in fz.iter().zip(fx.iter()).zip(alpha_pows.iter()) {
+                numerator = numerator + *alpha_pow * (BabyBearExtElem::from_subfield(fx) - *fz);
+                sum_z = sum_z + *alpha_pow * *fz;
+            }
+            let expected = numerator * (x_ext - z).inv();
+
+            let push = |x: &BabyBearExtElem| {
+                script! {
+                    for y in x.elems().iter().rev() {
+                        { y.as_u32() }
+                    }
+                }
+            };
+            let script = script! {
+                { push(&hint) }
+                { push(&z) }
+                { push(&alpha) }
+                for f in fz.iter() {
+                    { push(f) }
+                }
+                { x.as_u32() }
+                for f in fx.iter() {
+                    { f.as_u32() }
+                }
+                { deep_quotient_checked::<BabyBear4>(n_columns) }
+                { push(&expected) }
+                { u31ext_equalverify::<BabyBear4>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            let hint: &[BabyBearElem] = hint.elems();
+            let z: &[BabyBearElem] = z.elems();
+            let sum_z: &[BabyBearElem] = sum_z.elems();
+            let expected: &[BabyBearElem] = expected.elems();
+
+            let script = script! {
+                { hint[3].as_u32() } { hint[2].as_u32() } { hint[1].as_u32() } { hint[0].as_u32() }
+                { z[3].as_u32() } { z[2].as_u32() } { z[1].as_u32() } { z[0].as_u32() }
+                { sum_z[3].as_u32() } { sum_z[2].as_u32() } { sum_z[1].as_u32() } { sum_z[0].as_u32() }
+                { x.as_u32() }
+                for (alpha_pow, fx) in alpha_pows.iter().zip(fx.iter()) {
+                    { alpha_pow.elems()[3].as_u32() } { alpha_pow.elems()[2].as_u32() }
+                    { alpha_pow.elems()[1].as_u32() } { alpha_pow.elems()[0].as_u32() }
+                    { fx.as_u32() }
+                }
+                { deep_quotient::<BabyBear4>(n_columns) }
+                { expected[3].as_u32() } { expected[2].as_u32() } { expected[1].as_u32() } { expected[0].as_u32() }
+                { u31ext_equalverify::<BabyBear4>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_deep_quotient_qm31() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!(
+            "qm31 deep quotient (8 columns): {}",
+            deep_quotient::<QM31>(8).len()
+        );
+        eprintln!(
+            "qm31 deep quotient checked (8 columns): {}",
+            deep_quotient_checked::<QM31>(8).len()
+        );
+
+        let push = |x: &F| {
+            let x: &[Complex<p3_mersenne_31::Mersenne31>] = x.as_base_slice();
+            script! {
+                { x[1].imag().as_canonical_u32() }
+                { x[1].real().as_canonical_u32() }
+                { x[0].imag().as_canonical_u32() }
+                { x[0].real().as_canonical_u32() }
+            }
+        };
+
+        for n_columns in [1, 2, 8] {
+            let z = rng.gen::<F>();
+            let alpha = rng.gen::<F>();
+            let x = rng.gen::<p3_mersenne_31::Mersenne31>();
+
+            let fz: Vec<F> = (0..n_columns).map(|_| rng.gen::<F>()).collect();
+            let fx: Vec<p3_mersenne_31::Mersenne31> = (0..n_columns).map(|_| rng.gen()).collect();
+
+            let x_ext = F::from_base(Complex::new(x, p3_mersenne_31::Mersenne31::zero()));
+            let hint = (z - x_ext).inverse();
+
+            let alpha_pows: Vec<F> = alpha.powers().take(n_columns).collect();
+
+            let mut numerator = F::zero();
+            let mut sum_z = F::zero();
+            for ((fz, fx), alpha_pow) in fz.iter().zip(fx.iter()).zip(alpha_pows.iter()) {
+                let fx = F::from_base(Complex::new(*fx, p3_mersenne_31::Mersenne31::zero()));
+                numerator += *alpha_pow * (fx - *fz);
+                sum_z += *alpha_pow * *fz;
+            }
+            let expected = numerator * (x_ext - z).inverse();
+
+            let script = script! {
+                { push(&hint) }
+                { push(&z) }
+                { push(&alpha) }
+                for f in fz.iter() {
+                    { push(f) }
+                }
+                { x.as_canonical_u32() }
+                for f in fx.iter() {
+                    { f.as_canonical_u32() }
+                }
+                { deep_quotient_checked::<QM31>(n_columns) }
+                { push(&expected) }
+                { u31ext_equalverify::<QM31>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            let hint: &[Complex<p3_mersenne_31::Mersenne31>] = hint.as_base_slice();
+            let z: &[Complex<p3_mersenne_31::Mersenne31>] = z.as_base_slice();
+            let sum_z: &[Complex<p3_mersenne_31::Mersenne31>] = sum_z.as_base_slice();
+            let expected: &[Complex<p3_mersenne_31::Mersenne31>] = expected.as_base_slice();
+
+            let script = script! {
+                { hint[1].imag().as_canonical_u32() } { hint[1].real().as_canonical_u32() }
+                { hint[0].imag().as_canonical_u32() } { hint[0].real().as_canonical_u32() }
+                { z[1].imag().as_canonical_u32() } { z[1].real().as_canonical_u32() }
+                { z[0].imag().as_canonical_u32() } { z[0].real().as_canonical_u32() }
+                { sum_z[1].imag().as_canonical_u32() } { sum_z[1].real().as_canonical_u32() }
+                { sum_z[0].imag().as_canonical_u32() } { sum_z[0].real().as_canonical_u32() }
+                { x.as_canonical_u32() }
+                for (alpha_pow, fx) in alpha_pows.iter().zip(fx.iter()) {
+                    { alpha_pow.as_base_slice()[1].imag().as_canonical_u32() }
+                    { alpha_pow.as_base_slice()[1].real().as_canonical_u32() }
+                    { alpha_pow.as_base_slice()[0].imag().as_canonical_u32() }
+                    { alpha_pow.as_base_slice()[0].real().as_canonical_u32() }
+                    { fx.as_canonical_u32() }
+                }
+                { deep_quotient::<QM31>(n_columns) }
+                { expected[1].imag().as_canonical_u32() } { expected[1].real().as_canonical_u32() }
+                { expected[0].imag().as_canonical_u32() } { expected[0].real().as_canonical_u32() }
+                { u31ext_equalverify::<QM31>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+}
@@ -0,0 +1,729 @@
+use crate::{
+    deep_quotient, deep_quotient_checked, karatsuba_big, karatsuba_complex_big,
+    karatsuba_complex_small, karatsuba_small, lagrange_first_row, lagrange_last_row,
+    merkle_hash_node, merkle_verify_path, poseidon2_external_layer, poseidon2_internal_layer,
+    poseidon2_permute, poseidon2_sbox, qm31_complex_conjugate, qm31_pair_vanishing, u31_add,
+    u31_add_v31, u31_adjust, u31_batch_inv, u31_batch_inv_hinted, u31_commit_and_check,
+    u31_commit_and_verify, u31_double, u31_from_bits, u31_from_nibbles, u31_from_u32_limbs,
+    u31_mul, u31_mul_by_constant, u31_mul_hinted, u31_mul_many, u31_mul_many_windowed,
+    u31_mul_windowed, u31_neg, u31_sub, u31_to_bits, u31_to_bits_lsb_first, u31_to_bits_n,
+    u31_to_le_bytes, u31_to_nibbles, u31_to_u32_limbs, u31_to_v31, u31_vec_hash, u31ext_add,
+    u31ext_batch_inv, u31ext_batch_inv_hinted, u31ext_commit_and_check, u31ext_commit_and_verify,
+    u31ext_copy, u31ext_double, u31ext_equalverify, u31ext_equalverify_one, u31ext_fromaltstack,
+    u31ext_hash, u31ext_many_mul_u31, u31ext_mul, u31ext_mul_u31, u31ext_mul_u31_by_constant,
+    u31ext_roll, u31ext_sub, u31ext_to_nibbles, u31ext_toaltstack, u32_reduce, u64_reduce, v31_add,
+    v31_add_u31, v31_adjust, v31_double, v31_neg, v31_sub, v31_to_u31, vanishing_eval,
+    DomainConfig, Poseidon2Config, U31Config, U31ExtConfig, COMMIT_SIGNATURE_LEN, POSEIDON2_WIDTH,
+    U31_MUL_HINT_LEN,
+};
+use bitvm::signatures::winternitz::PublicKey;
+use bitvm::treepp::*;
+
+// A script with its stack effect: it takes the top `inputs` elements of the stack, leaves
+// `outputs` elements in their place, and changes the size of the altstack by `altstack_delta`.
+pub struct FieldScript {
+    pub script: Script,
+    pub inputs: usize,
+    pub outputs: usize,
+    pub altstack_delta: isize,
+}
+
+impl FieldScript {
+    pub fn new(script: Script, inputs: usize, outputs: usize) -> Self {
+        Self {
+            script,
+            inputs,
+            outputs,
+            altstack_delta: 0,
+        }
+    }
+
+    pub fn with_altstack_delta(mut self, altstack_delta: isize) -> Self {
+        self.altstack_delta = altstack_delta;
+        self
+    }
+
+    // self followed by next, where next may reach below the outputs of self
+    pub fn then(self, next: FieldScript) -> Self {
+        let (inputs, outputs) = if next.inputs <= self.outputs {
+            (self.inputs, self.outputs - next.inputs + next.outputs)
+        } else {
+            (self.inputs + next.inputs - self.outputs, next.outputs)
+        };
+
+        let mut script_bytes = self.script.as_bytes().to_vec();
+        script_bytes.extend_from_slice(next.script.as_bytes());
+
+        Self {
+            script: Script::from(script_bytes),
+            inputs,
+            outputs,
+            altstack_delta: self.altstack_delta + next.altstack_delta,
+        }
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.altstack_delta == 0
+    }
+
+    // Runs the script on the given inputs (the last one on top) and checks that it succeeds
+    // with exactly `outputs` elements left on the stack and `altstack_delta` more elements on
+    // the altstack. A script that consumes elements from the altstack is given zeros.
+    pub fn check<T: Copy + Into<i64>>(&self, inputs: &[T]) -> bool {
+        assert_eq!(inputs.len(), self.inputs);
+        let pre_altstack = (-self.altstack_delta).max(0) as usize;
+        let post_altstack = self.altstack_delta.max(0) as usize;
+
+        let script = script! {
+            for _ in 0..pre_altstack {
+                0 OP_TOALTSTACK
+            }
+            for x in inputs.iter() {
+                { Into::<i64>::into(*x) }
+            }
+            { self.script.clone() }
+            OP_DEPTH { self.outputs } OP_EQUALVERIFY
+            for _ in 0..self.outputs {
+                OP_DROP
+            }
+            for _ in 0..post_altstack {
+                OP_FROMALTSTACK OP_DROP
+            }
+            OP_TRUE
+        };
+        execute_script(script).success
+    }
+
+    pub fn u31_add<M: U31Config>() -> Self {
+        Self::new(u31_add::<M>(), 2, 1)
+    }
+
+    pub fn u31_sub<M: U31Config>() -> Self {
+        Self::new(u31_sub::<M>(), 2, 1)
+    }
+
+    pub fn u31_double<M: U31Config>() -> Self {
+        Self::new(u31_double::<M>(), 1, 1)
+    }
+
+    pub fn u31_neg<M: U31Config>() -> Self {
+        Self::new(u31_neg::<M>(), 1, 1)
+    }
+
+    pub fn u31_to_v31<M: U31Config>() -> Self {
+        Self::new(u31_to_v31::<M>(), 1, 1)
+    }
+
+    pub fn v31_to_u31<M: U31Config>() -> Self {
+        Self::new(v31_to_u31::<M>(), 1, 1)
+    }
+
+    pub fn u31_adjust<M: U31Config>() -> Self {
+        Self::new(u31_adjust::<M>(), 1, 1)
+    }
+
+    pub fn v31_adjust<M: U31Config>() -> Self {
+        Self::new(v31_adjust::<M>(), 1, 1)
+    }
+
+    pub fn u31_add_v31<M: U31Config>() -> Self {
+        Self::new(u31_add_v31::<M>(), 2, 1)
+    }
+
+    pub fn v31_add_u31<M: U31Config>() -> Self {
+        Self::new(v31_add_u31::<M>(), 2, 1)
+    }
+
+    pub fn v31_add<M: U31Config>() -> Self {
+        Self::new(v31_add::<M>(), 2, 1)
+    }
+
+    pub fn v31_sub<M: U31Config>() -> Self {
+        Self::new(v31_sub::<M>(), 2, 1)
+    }
+
+    pub fn v31_double<M: U31Config>() -> Self {
+        Self::new(v31_double::<M>(), 1, 1)
+    }
+
+    pub fn v31_neg<M: U31Config>() -> Self {
+        Self::new(v31_neg::<M>(), 1, 1)
+    }
+
+    pub fn u31_mul<M: U31Config>() -> Self {
+        Self::new(u31_mul::<M>(), 2, 1)
+    }
+
+    pub fn u31_mul_by_constant<M: U31Config>(constant: u32) -> Self {
+        Self::new(u31_mul_by_constant::<M>(constant), 1, 1)
+    }
+
+    pub fn u31_to_bits() -> Self {
+        Self::new(u31_to_bits(), 1, 31)
+    }
+
+    pub fn u31_to_bits_n(n: u32) -> Self {
+        Self::new(u31_to_bits_n(n), 1, n as usize)
+    }
+
+    pub fn u31_to_bits_lsb_first(n: u32) -> Self {
+        Self::new(u31_to_bits_lsb_first(n), 1, n as usize)
+    }
+
+    pub fn u31_from_bits(n: u32) -> Self {
+        Self::new(u31_from_bits(n), n as usize, 1)
+    }
+
+    pub fn u31_mul_hinted<M: U31Config>() -> Self {
+        Self::new(u31_mul_hinted::<M>(), U31_MUL_HINT_LEN + 2, 1)
+    }
+
+    pub fn u31_mul_windowed<M: U31Config>(w: u32) -> Self {
+        Self::new(u31_mul_windowed::<M>(w), 2, 1)
+    }
+
+    pub fn u31_mul_many<M: U31Config>(n: usize) -> Self {
+        Self::new(u31_mul_many::<M>(n), n + 1, n)
+    }
+
+    pub fn u31_mul_many_windowed<M: U31Config>(n: usize, w: u32) -> Self {
+        Self::new(u31_mul_many_windowed::<M>(n, w), n + 1, n)
+    }
+
+    pub fn u31_batch_inv<M: U31Config>(n: usize) -> Self {
+        Self::new(u31_batch_inv::<M>(n), n + 1, n)
+    }
+
+    pub fn u31_batch_inv_hinted<M: U31Config>(n: usize) -> Self {
+        Self::new(u31_batch_inv_hinted::<M>(n), 2 * n, n)
+    }
+
+    pub fn u31_to_u32_limbs() -> Self {
+        Self::new(u31_to_u32_limbs(), 1, 4)
+    }
+
+    pub fn u31_from_u32_limbs<M: U31Config>() -> Self {
+        Self::new(u31_from_u32_limbs::<M>(), 4, 1)
+    }
+
+    pub fn u32_reduce<M: U31Config>() -> Self {
+        Self::new(u32_reduce::<M>(), 2, 1)
+    }
+
+    pub fn u64_reduce<M: U31Config>() -> Self {
+        Self::new(u64_reduce::<M>(), 4, 1)
+    }
+
+    pub fn u31_to_nibbles() -> Self {
+        Self::new(u31_to_nibbles(), 1, 8)
+    }
+
+    pub fn u31_from_nibbles() -> Self {
+        Self::new(u31_from_nibbles(), 8, 1)
+    }
+
+    pub fn u31_to_le_bytes<M: U31Config>() -> Self {
+        Self::new(u31_to_le_bytes::<M>(), 1, 1)
+    }
+
+    pub fn u31_vec_hash<M: U31Config>(n: u32) -> Self {
+        Self::new(u31_vec_hash::<M>(n), n as usize, 1)
+    }
+
+    pub fn u31_commit_and_verify<M: U31Config>(public_key: &PublicKey) -> Self {
+        Self::new(
+            u31_commit_and_verify::<M>(public_key),
+            COMMIT_SIGNATURE_LEN,
+            1,
+        )
+    }
+
+    pub fn u31_commit_and_check<M: U31Config>(public_key: &PublicKey) -> Self {
+        Self::new(
+            u31_commit_and_check::<M>(public_key),
+            COMMIT_SIGNATURE_LEN,
+            2,
+        )
+    }
+
+    pub fn karatsuba_small<M: U31Config>() -> Self {
+        Self::new(karatsuba_small::<M>(), 4, 3)
+    }
+
+    pub fn karatsuba_big<M: U31Config>() -> Self {
+        Self::new(karatsuba_big::<M>(), 8, 9)
+    }
+
+    pub fn karatsuba_complex_small<M: U31Config>() -> Self {
+        Self::new(karatsuba_complex_small::<M>(), 4, 2)
+    }
+
+    pub fn karatsuba_complex_big<M: U31Config>() -> Self {
+        Self::new(karatsuba_complex_big::<M>(), 8, 6)
+    }
+
+    pub fn u31ext_add<C: U31ExtConfig>() -> Self {
+        Self::new(u31ext_add::<C>(), 2 * degree::<C>(), degree::<C>())
+    }
+
+    pub fn u31ext_sub<C: U31ExtConfig>() -> Self {
+        Self::new(u31ext_sub::<C>(), 2 * degree::<C>(), degree::<C>())
+    }
+
+    pub fn u31ext_double<C: U31ExtConfig>() -> Self {
+        Self::new(u31ext_double::<C>(), degree::<C>(), degree::<C>())
+    }
+
+    pub fn u31ext_mul<C: U31ExtConfig>() -> Self {
+        Self::new(u31ext_mul::<C>(), 2 * degree::<C>(), degree::<C>())
+    }
+
+    pub fn u31ext_mul_u31<C: U31ExtConfig>() -> Self {
+        Self::new(u31ext_mul_u31::<C>(), degree::<C>() + 1, degree::<C>())
+    }
+
+    pub fn u31ext_mul_u31_by_constant<C: U31ExtConfig>(constant: u32) -> Self {
+        Self::new(
+            u31ext_mul_u31_by_constant::<C>(constant),
+            degree::<C>(),
+            degree::<C>(),
+        )
+    }
+
+    pub fn u31ext_many_mul_u31<C: U31ExtConfig>(n: usize) -> Self {
+        let limbs = n * degree::<C>();
+        Self::new(u31ext_many_mul_u31::<C>(n), limbs + 1, limbs)
+    }
+
+    pub fn u31ext_batch_inv<C: U31ExtConfig>(n: usize) -> Self {
+        Self::new(
+            u31ext_batch_inv::<C>(n),
+            (n + 1) * degree::<C>(),
+            n * degree::<C>(),
+        )
+    }
+
+    pub fn u31ext_batch_inv_hinted<C: U31ExtConfig>(n: usize) -> Self {
+        Self::new(
+            u31ext_batch_inv_hinted::<C>(n),
+            2 * n * degree::<C>(),
+            n * degree::<C>(),
+        )
+    }
+
+    pub fn u31ext_to_nibbles<C: U31ExtConfig>() -> Self {
+        Self::new(u31ext_to_nibbles::<C>(), degree::<C>(), 8 * degree::<C>())
+    }
+
+    pub fn u31ext_hash<C: U31ExtConfig>() -> Self {
+        Self::new(u31ext_hash::<C>(), degree::<C>(), 1)
+    }
+
+    pub fn u31ext_commit_and_verify<C: U31ExtConfig>(public_key: &PublicKey) -> Self {
+        Self::new(
+            u31ext_commit_and_verify::<C>(public_key),
+            COMMIT_SIGNATURE_LEN,
+            degree::<C>(),
+        )
+    }
+
+    pub fn u31ext_commit_and_check<C: U31ExtConfig>(public_key: &PublicKey) -> Self {
+        Self::new(
+            u31ext_commit_and_check::<C>(public_key),
+            COMMIT_SIGNATURE_LEN,
+            degree::<C>() + 1,
+        )
+    }
+
+    // the root, the siblings and their bits, and the leaf
+    pub fn merkle_verify_path<C: U31ExtConfig>(depth: usize) -> Self {
+        Self::new(
+            merkle_verify_path::<C>(depth),
+            1 + 2 * depth + degree::<C>(),
+            0,
+        )
+    }
+
+    pub fn merkle_hash_node() -> Self {
+        Self::new(merkle_hash_node(), 3, 1)
+    }
+
+    pub fn deep_quotient<C: U31ExtConfig>(n_columns: usize) -> Self {
+        let inputs = 3 * degree::<C>() + 1 + n_columns * (degree::<C>() + 1);
+        Self::new(deep_quotient::<C>(n_columns), inputs, degree::<C>())
+    }
+
+    pub fn deep_quotient_checked<C: U31ExtConfig>(n_columns: usize) -> Self {
+        let inputs = (3 + n_columns) * degree::<C>() + 1 + n_columns;
+        Self::new(deep_quotient_checked::<C>(n_columns), inputs, degree::<C>())
+    }
+
+    pub fn vanishing_eval<C: DomainConfig>(log_n: u32, coset_shift: C::CosetShift) -> Self {
+        Self::new(
+            vanishing_eval::<C>(log_n, coset_shift),
+            C::POINT_SIZE * degree::<C>(),
+            degree::<C>(),
+        )
+    }
+
+    // the hinted inverse, then the point
+    pub fn lagrange_first_row<C: DomainConfig>(log_n: u32, coset_shift: C::CosetShift) -> Self {
+        Self::new(
+            lagrange_first_row::<C>(log_n, coset_shift),
+            (C::POINT_SIZE + 1) * degree::<C>(),
+            degree::<C>(),
+        )
+    }
+
+    pub fn lagrange_last_row<C: DomainConfig>(log_n: u32, coset_shift: C::CosetShift) -> Self {
+        Self::new(
+            lagrange_last_row::<C>(log_n, coset_shift),
+            (C::POINT_SIZE + 1) * degree::<C>(),
+            degree::<C>(),
+        )
+    }
+
+    pub fn qm31_complex_conjugate() -> Self {
+        Self::new(qm31_complex_conjugate(), 4, 4)
+    }
+
+    // z.x, z.y (qm31), p.x, p.y (m31)
+    pub fn qm31_pair_vanishing() -> Self {
+        Self::new(qm31_pair_vanishing(), 10, 4)
+    }
+
+    pub fn u31ext_equalverify_one<C: U31ExtConfig>() -> Self {
+        Self::new(u31ext_equalverify_one::<C>(), degree::<C>(), 0)
+    }
+
+    pub fn u31ext_equalverify<C: U31ExtConfig>() -> Self {
+        Self::new(u31ext_equalverify::<C>(), 2 * degree::<C>(), 0)
+    }
+
+    pub fn u31ext_toaltstack<C: U31ExtConfig>() -> Self {
+        Self::new(u31ext_toaltstack::<C>(), degree::<C>(), 0)
+            .with_altstack_delta(degree::<C>() as isize)
+    }
+
+    pub fn u31ext_fromaltstack<C: U31ExtConfig>() -> Self {
+        Self::new(u31ext_fromaltstack::<C>(), 0, degree::<C>())
+            .with_altstack_delta(-(degree::<C>() as isize))
+    }
+
+    pub fn u31ext_copy<C: U31ExtConfig>(offset: usize) -> Self {
+        let inputs = (offset + 1) * degree::<C>();
+        Self::new(u31ext_copy::<C>(offset), inputs, inputs + degree::<C>())
+    }
+
+    pub fn u31ext_roll<C: U31ExtConfig>(offset: usize) -> Self {
+        let inputs = (offset + 1) * degree::<C>();
+        Self::new(u31ext_roll::<C>(offset), inputs, inputs)
+    }
+
+    pub fn poseidon2_sbox<C: Poseidon2Config>() -> Self {
+        Self::new(poseidon2_sbox::<C>(), 1, 1)
+    }
+
+    pub fn poseidon2_external_layer<C: Poseidon2Config>() -> Self {
+        Self::new(
+            poseidon2_external_layer::<C>(),
+            POSEIDON2_WIDTH,
+            POSEIDON2_WIDTH,
+        )
+    }
+
+    pub fn poseidon2_internal_layer<C: Poseidon2Config>() -> Self {
+        Self::new(
+            poseidon2_internal_layer::<C>(),
+            POSEIDON2_WIDTH,
+            POSEIDON2_WIDTH,
+        )
+    }
+
+    pub fn poseidon2_permute<C: Poseidon2Config>(
+        external_constants: &[[u32; POSEIDON2_WIDTH]],
+        internal_constants: &[u32],
+    ) -> Self {
+        Self::new(
+            poseidon2_permute::<C>(external_constants, internal_constants),
+            POSEIDON2_WIDTH,
+            POSEIDON2_WIDTH,
+        )
+    }
+}
+
+fn degree<C: U31ExtConfig>() -> usize {
+    C::DEGREE as usize
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        u31_inv_native, u31_mul_hint, u31_to_nibbles_native, BabyBear, BabyBear4, FieldScript,
+        Poseidon2BabyBear, Poseidon2Config, Poseidon2M31, U31Config, U31ExtConfig, M31,
+        POSEIDON2_WIDTH, QM31,
+    };
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn random_inputs<M: U31Config>(prng: &mut ChaCha20Rng, n: usize) -> Vec<u32> {
+        (0..n).map(|_| prng.gen_range(0..M::MOD)).collect()
+    }
+
+    fn test_u31_stack_effects_generic<M: U31Config>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+        for fs in [
+            FieldScript::u31_add::<M>(),
+            FieldScript::u31_sub::<M>(),
+            FieldScript::u31_double::<M>(),
+            FieldScript::u31_neg::<M>(),
+            FieldScript::u31_mul::<M>(),
+            FieldScript::u31_mul_by_constant::<M>(prng.gen_range(0..M::MOD)),
+            FieldScript::u31_to_bits(),
+            FieldScript::karatsuba_small::<M>(),
+            FieldScript::karatsuba_big::<M>(),
+        ] {
+            assert!(fs.is_balanced());
+            assert!(fs.check(&random_inputs::<M>(&mut prng, fs.inputs)));
+        }
+
+        let fs = FieldScript::u31_to_bits_n(10).then(FieldScript::u31_from_bits(10));
+        assert_eq!((fs.inputs, fs.outputs), (1, 1));
+        assert!(fs.check(&[prng.gen_range(0..1 << 10)]));
+    }
+
+    fn test_u31ext_stack_effects_generic<C: U31ExtConfig>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+        let degree = C::DEGREE as usize;
+
+        for fs in [
+            FieldScript::u31ext_add::<C>(),
+            FieldScript::u31ext_sub::<C>(),
+            FieldScript::u31ext_double::<C>(),
+            FieldScript::u31ext_mul::<C>(),
+            FieldScript::u31ext_mul_u31::<C>(),
+            FieldScript::u31ext_mul_u31_by_constant::<C>(
+                prng.gen_range(0..<C::BaseFieldConfig as U31Config>::MOD),
+            ),
+            FieldScript::u31ext_copy::<C>(2),
+            FieldScript::u31ext_roll::<C>(2),
+            FieldScript::u31ext_toaltstack::<C>(),
+            FieldScript::u31ext_fromaltstack::<C>(),
+        ] {
+            assert!(fs.check(&random_inputs::<C::BaseFieldConfig>(&mut prng, fs.inputs)));
+        }
+
+        // (a * b + a) * b, with a kept on the altstack in between
+        let fs = FieldScript::u31ext_copy::<C>(1)
+            .then(FieldScript::u31ext_toaltstack::<C>())
+            .then(FieldScript::u31ext_copy::<C>(0))
+            .then(FieldScript::u31ext_toaltstack::<C>())
+            .then(FieldScript::u31ext_mul::<C>())
+            .then(FieldScript::u31ext_fromaltstack::<C>())
+            .then(FieldScript::u31ext_fromaltstack::<C>())
+            .then(FieldScript::u31ext_roll::<C>(2))
+            .then(FieldScript::u31ext_add::<C>())
+            .then(FieldScript::u31ext_mul::<C>());
+        assert!(fs.is_balanced());
+        assert_eq!((fs.inputs, fs.outputs), (2 * degree, degree));
+        assert!(fs.check(&random_inputs::<C::BaseFieldConfig>(&mut prng, 2 * degree)));
+
+        // leaving an element on the altstack is reported
+        let fs = FieldScript::u31ext_toaltstack::<C>().then(FieldScript::u31ext_double::<C>());
+        assert!(!fs.is_balanced());
+        assert_eq!((fs.inputs, fs.outputs), (2 * degree, degree));
+    }
+
+    fn to_i64(v: &[u32]) -> Vec<i64> {
+        v.iter().map(|x| *x as i64).collect()
+    }
+
+    fn product<M: U31Config>(v: &[u32]) -> u32 {
+        v.iter()
+            .fold(1u64, |acc, x| acc * *x as u64 % M::MOD as u64) as u32
+    }
+
+    // a (u31) as an element of the extension, limb 0 on top
+    fn embed<C: U31ExtConfig>(a: u32) -> Vec<i64> {
+        let mut limbs = vec![0; C::DEGREE as usize - 1];
+        limbs.push(a as i64);
+        limbs
+    }
+
+    // Runs each primitive on inputs for which it succeeds, which checks its input and output
+    // counts against what the script actually does.
+    fn test_u31_primitive_stack_effects_generic<M: U31Config>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+        let a = prng.gen_range(0..M::MOD);
+        let b = prng.gen_range(0..M::MOD);
+        let x: u32 = prng.gen();
+        let y: u64 = prng.gen();
+        let ys = random_inputs::<M>(&mut prng, 3);
+        let nonzero: Vec<u32> = (0..4).map(|_| prng.gen_range(1..M::MOD)).collect();
+        let h = u31_inv_native::<M>(product::<M>(&nonzero));
+        let nonzero_inv: Vec<u32> = nonzero.iter().map(|a| u31_inv_native::<M>(*a)).collect();
+
+        let table = [
+            (
+                FieldScript::u31_mul_hinted::<M>(),
+                [u31_mul_hint::<M>(a, b), to_i64(&[a, b])].concat(),
+            ),
+            (FieldScript::u31_mul_windowed::<M>(3), to_i64(&[a, b])),
+            (
+                FieldScript::u31_mul_many::<M>(3),
+                to_i64(&[&ys[..], &[a]].concat()),
+            ),
+            (
+                FieldScript::u31_mul_many_windowed::<M>(3, 4),
+                to_i64(&[&ys[..], &[a]].concat()),
+            ),
+            (
+                FieldScript::u31_batch_inv::<M>(4),
+                to_i64(&[&[h], &nonzero[..]].concat()),
+            ),
+            (
+                FieldScript::u31_batch_inv_hinted::<M>(4),
+                to_i64(&[&nonzero[..], &nonzero_inv[..]].concat()),
+            ),
+            (FieldScript::u31_to_u32_limbs(), to_i64(&[a])),
+            (
+                FieldScript::u31_from_u32_limbs::<M>(),
+                to_i64(&x.to_be_bytes().map(|byte| byte as u32)),
+            ),
+            (
+                FieldScript::u32_reduce::<M>(),
+                to_i64(&[x >> 16, x & 0xffff]),
+            ),
+            (
+                FieldScript::u64_reduce::<M>(),
+                (0..4)
+                    .rev()
+                    .map(|i| (y >> (16 * i)) as i64 & 0xffff)
+                    .collect(),
+            ),
+            (FieldScript::u31_to_nibbles(), to_i64(&[a])),
+            (
+                FieldScript::u31_from_nibbles(),
+                u31_to_nibbles_native(a)
+                    .iter()
+                    .rev()
+                    .map(|nibble| *nibble as i64)
+                    .collect(),
+            ),
+        ];
+        for (fs, inputs) in table {
+            assert!(fs.is_balanced());
+            assert!(fs.check(&inputs));
+        }
+    }
+
+    fn test_u31ext_primitive_stack_effects_generic<C: U31ExtConfig>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+        let degree = C::DEGREE as usize;
+        let modulus = <C::BaseFieldConfig as U31Config>::MOD;
+
+        // the inverses of base field elements are the only ones the checks need
+        let nonzero: Vec<u32> = (0..3).map(|_| prng.gen_range(1..modulus)).collect();
+        let h = u31_inv_native::<C::BaseFieldConfig>(product::<C::BaseFieldConfig>(&nonzero));
+        let nonzero_inv: Vec<u32> = nonzero
+            .iter()
+            .map(|a| u31_inv_native::<C::BaseFieldConfig>(*a))
+            .collect();
+        let x = prng.gen_range(0..modulus);
+        let z = (x + prng.gen_range(1..modulus)) % modulus;
+        let z_minus_x_inv = u31_inv_native::<C::BaseFieldConfig>((z + modulus - x) % modulus);
+
+        let mut random = |n: usize| to_i64(&random_inputs::<C::BaseFieldConfig>(&mut prng, n));
+        let table = [
+            (
+                FieldScript::u31ext_many_mul_u31::<C>(3),
+                random(3 * degree + 1),
+            ),
+            (
+                FieldScript::u31ext_batch_inv::<C>(3),
+                [h, nonzero[0], nonzero[1], nonzero[2]]
+                    .iter()
+                    .flat_map(|a| embed::<C>(*a))
+                    .collect(),
+            ),
+            (
+                FieldScript::u31ext_batch_inv_hinted::<C>(3),
+                nonzero
+                    .iter()
+                    .chain(nonzero_inv.iter())
+                    .flat_map(|a| embed::<C>(*a))
+                    .collect(),
+            ),
+            (FieldScript::u31ext_to_nibbles::<C>(), random(degree)),
+            (
+                FieldScript::deep_quotient::<C>(2),
+                [
+                    embed::<C>(z_minus_x_inv),
+                    embed::<C>(z),
+                    random(degree),
+                    vec![x as i64],
+                    random(2 * (degree + 1)),
+                ]
+                .concat(),
+            ),
+            (
+                FieldScript::deep_quotient_checked::<C>(2),
+                [
+                    embed::<C>(z_minus_x_inv),
+                    embed::<C>(z),
+                    random(3 * degree),
+                    vec![x as i64],
+                    random(2),
+                ]
+                .concat(),
+            ),
+            (FieldScript::u31ext_equalverify_one::<C>(), embed::<C>(1)),
+        ];
+        for (fs, inputs) in table {
+            assert!(fs.is_balanced());
+            assert!(fs.check(&inputs));
+        }
+    }
+
+    fn test_poseidon2_stack_effects_generic<P: Poseidon2Config>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+        let modulus = <P::BaseFieldConfig as U31Config>::MOD;
+
+        let external_constants: Vec<[u32; POSEIDON2_WIDTH]> = (0..P::ROUNDS_F)
+            .map(|_| core::array::from_fn(|_| prng.gen_range(0..modulus)))
+            .collect();
+        let internal_constants: Vec<u32> = (0..P::ROUNDS_P)
+            .map(|_| prng.gen_range(0..modulus))
+            .collect();
+
+        for fs in [
+            FieldScript::poseidon2_sbox::<P>(),
+            FieldScript::poseidon2_external_layer::<P>(),
+            FieldScript::poseidon2_internal_layer::<P>(),
+            FieldScript::poseidon2_permute::<P>(&external_constants, &internal_constants),
+        ] {
+            assert!(fs.is_balanced());
+            assert!(fs.check(&random_inputs::<P::BaseFieldConfig>(&mut prng, fs.inputs)));
+        }
+    }
+
+    #[test]
+    fn test_primitive_stack_effects() {
+        test_u31_primitive_stack_effects_generic::<M31>(4u64);
+        test_u31_primitive_stack_effects_generic::<BabyBear>(5u64);
+        test_u31ext_primitive_stack_effects_generic::<QM31>(6u64);
+        test_u31ext_primitive_stack_effects_generic::<BabyBear4>(7u64);
+        test_poseidon2_stack_effects_generic::<Poseidon2M31>(8u64);
+        test_poseidon2_stack_effects_generic::<Poseidon2BabyBear>(9u64);
+    }
+
+    #[test]
+    fn test_stack_effects() {
+        test_u31_stack_effects_generic::<M31>(0u64);
+        test_u31_stack_effects_generic::<BabyBear>(1u64);
+        test_u31ext_stack_effects_generic::<QM31>(2u64);
+        test_u31ext_stack_effects_generic::<BabyBear4>(3u64);
+    }
+}
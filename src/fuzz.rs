@@ -0,0 +1,543 @@
+// Differential fuzzing of the primitives against Plonky3 (M31, QM31) and RISC Zero (BabyBear,
+// BabyBear4).
+//
+// Each sequence starts from a few random elements, biased towards the edge values 0, 1, MOD - 1
+// and 2^30, applies random operations to them with the primitives, and checks the stack that
+// execute_script leaves against the reference implementation. The extension field
+// multiplication covers the hand-tuned karatsuba scripts, and the hinted multiplication and the
+// batch inverses are given the hints an honest prover would supply. FUZZ_SEQUENCES sets the
+// number of sequences per field (20 by default).
+
+use crate::{
+    u31_add, u31_batch_inv, u31_double, u31_from_bits, u31_from_nibbles, u31_from_u32_limbs,
+    u31_mul, u31_mul_by_constant, u31_mul_hint, u31_mul_hinted, u31_mul_many, u31_mul_windowed,
+    u31_neg, u31_sub, u31_to_bits, u31_to_nibbles, u31_to_u32_limbs, u31ext_add, u31ext_batch_inv,
+    u31ext_copy, u31ext_double, u31ext_equalverify, u31ext_many_mul_u31, u31ext_mul,
+    u31ext_mul_u31, u31ext_mul_u31_by_constant, u31ext_roll, u31ext_sub, u32_reduce, u64_reduce,
+    BabyBear, BabyBear4, DualScript, U31Config, U31ExtConfig, M31, QM31, U31, U31_MUL_HINT_LEN,
+};
+use bitvm::treepp::*;
+use p3_field::extension::Complex;
+use p3_field::{AbstractExtensionField, AbstractField, Field, PrimeField32};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use risc0_core::field::baby_bear::{BabyBearElem, BabyBearExtElem};
+use risc0_core::field::Elem;
+
+type P3M31 = p3_mersenne_31::Mersenne31;
+type P3QM31 = p3_field::extension::BinomialExtensionField<Complex<P3M31>, 2>;
+
+const SEQUENCE_LENGTH: usize = 16;
+
+fn sequences() -> usize {
+    std::env::var("FUZZ_SEQUENCES")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(20)
+}
+
+fn random_value<M: U31Config>(prng: &mut ChaCha20Rng) -> u32 {
+    match prng.gen_range(0..8) {
+        0 => 0,
+        1 => 1,
+        2 => M::MOD - 1,
+        3 => 1 << 30,
+        _ => prng.gen_range(0..M::MOD),
+    }
+}
+
+// A reference implementation of a base field, on canonical values
+trait BaseReference {
+    type M: U31Config;
+
+    fn add(a: u32, b: u32) -> u32;
+    fn sub(a: u32, b: u32) -> u32;
+    fn mul(a: u32, b: u32) -> u32;
+    fn inv(a: u32) -> u32;
+}
+
+// A reference implementation of an extension field, on its canonical limbs, in the order in
+// which the scripts take them from the top of the stack
+trait ExtReference {
+    type C: U31ExtConfig;
+
+    fn add(a: &[u32], b: &[u32]) -> Vec<u32>;
+    fn sub(a: &[u32], b: &[u32]) -> Vec<u32>;
+    fn mul(a: &[u32], b: &[u32]) -> Vec<u32>;
+    fn mul_base(a: &[u32], b: u32) -> Vec<u32>;
+    fn inv(a: &[u32]) -> Vec<u32>;
+}
+
+struct Plonky3M31;
+
+impl BaseReference for Plonky3M31 {
+    type M = M31;
+
+    fn add(a: u32, b: u32) -> u32 {
+        (P3M31::from_canonical_u32(a) + P3M31::from_canonical_u32(b)).as_canonical_u32()
+    }
+
+    fn sub(a: u32, b: u32) -> u32 {
+        (P3M31::from_canonical_u32(a) - P3M31::from_canonical_u32(b)).as_canonical_u32()
+    }
+
+    fn mul(a: u32, b: u32) -> u32 {
+        (P3M31::from_canonical_u32(a) * P3M31::from_canonical_u32(b)).as_canonical_u32()
+    }
+
+    fn inv(a: u32) -> u32 {
+        P3M31::from_canonical_u32(a).inverse().as_canonical_u32()
+    }
+}
+
+struct RiscZeroBabyBear;
+
+impl BaseReference for RiscZeroBabyBear {
+    type M = BabyBear;
+
+    fn add(a: u32, b: u32) -> u32 {
+        (BabyBearElem::new(a) + BabyBearElem::new(b)).as_u32()
+    }
+
+    fn sub(a: u32, b: u32) -> u32 {
+        (BabyBearElem::new(a) - BabyBearElem::new(b)).as_u32()
+    }
+
+    fn mul(a: u32, b: u32) -> u32 {
+        (BabyBearElem::new(a) * BabyBearElem::new(b)).as_u32()
+    }
+
+    fn inv(a: u32) -> u32 {
+        BabyBearElem::new(a).inv().as_u32()
+    }
+}
+
+struct Plonky3QM31;
+
+impl Plonky3QM31 {
+    fn complex(real: u32, imag: u32) -> Complex<P3M31> {
+        Complex::new(
+            P3M31::from_canonical_u32(real),
+            P3M31::from_canonical_u32(imag),
+        )
+    }
+
+    fn from_limbs(a: &[u32]) -> P3QM31 {
+        P3QM31::from_base_slice(&[Self::complex(a[0], a[1]), Self::complex(a[2], a[3])])
+    }
+
+    fn to_limbs(a: P3QM31) -> Vec<u32> {
+        a.as_base_slice()
+            .iter()
+            .flat_map(|c| [c.real().as_canonical_u32(), c.imag().as_canonical_u32()])
+            .collect()
+    }
+}
+
+impl ExtReference for Plonky3QM31 {
+    type C = QM31;
+
+    fn add(a: &[u32], b: &[u32]) -> Vec<u32> {
+        Self::to_limbs(Self::from_limbs(a) + Self::from_limbs(b))
+    }
+
+    fn sub(a: &[u32], b: &[u32]) -> Vec<u32> {
+        Self::to_limbs(Self::from_limbs(a) - Self::from_limbs(b))
+    }
+
+    fn mul(a: &[u32], b: &[u32]) -> Vec<u32> {
+        Self::to_limbs(Self::from_limbs(a) * Self::from_limbs(b))
+    }
+
+    fn mul_base(a: &[u32], b: u32) -> Vec<u32> {
+        let b = P3QM31::from_base(Self::complex(b, 0));
+        Self::to_limbs(Self::from_limbs(a) * b)
+    }
+
+    fn inv(a: &[u32]) -> Vec<u32> {
+        Self::to_limbs(Self::from_limbs(a).inverse())
+    }
+}
+
+struct RiscZeroBabyBear4;
+
+impl RiscZeroBabyBear4 {
+    fn from_limbs(a: &[u32]) -> BabyBearExtElem {
+        BabyBearExtElem::new(
+            BabyBearElem::new(a[0]),
+            BabyBearElem::new(a[1]),
+            BabyBearElem::new(a[2]),
+            BabyBearElem::new(a[3]),
+        )
+    }
+
+    fn to_limbs(a: BabyBearExtElem) -> Vec<u32> {
+        a.elems().iter().map(|x| x.as_u32()).collect()
+    }
+}
+
+impl ExtReference for RiscZeroBabyBear4 {
+    type C = BabyBear4;
+
+    fn add(a: &[u32], b: &[u32]) -> Vec<u32> {
+        Self::to_limbs(Self::from_limbs(a) + Self::from_limbs(b))
+    }
+
+    fn sub(a: &[u32], b: &[u32]) -> Vec<u32> {
+        Self::to_limbs(Self::from_limbs(a) - Self::from_limbs(b))
+    }
+
+    fn mul(a: &[u32], b: &[u32]) -> Vec<u32> {
+        Self::to_limbs(Self::from_limbs(a) * Self::from_limbs(b))
+    }
+
+    fn mul_base(a: &[u32], b: u32) -> Vec<u32> {
+        Self::to_limbs(Self::from_limbs(a) * BabyBearElem::new(b))
+    }
+
+    fn inv(a: &[u32]) -> Vec<u32> {
+        Self::to_limbs(Self::from_limbs(a).inv())
+    }
+}
+
+fn fuzz_u31<R: BaseReference>(seed: u64) {
+    let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+    for i in 0..sequences() {
+        let inputs: Vec<u32> = (0..3).map(|_| random_value::<R::M>(&mut prng)).collect();
+        let mut stack = inputs.clone();
+        let mut scripts = vec![];
+
+        for _ in 0..SEQUENCE_LENGTH {
+            let len = stack.len();
+            let script = match prng.gen_range(0..20) {
+                0 if len >= 2 => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(R::add(a, b));
+                    u31_add::<R::M>()
+                }
+                1 if len >= 2 => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(R::sub(a, b));
+                    u31_sub::<R::M>()
+                }
+                2 if len >= 2 => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(R::mul(a, b));
+                    u31_mul::<R::M>()
+                }
+                3 if len >= 2 => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(R::mul(a, b));
+                    u31_mul_windowed::<R::M>([1, 3, 4][prng.gen_range(0..3)])
+                }
+                4 if len >= 2 => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(R::mul(a, b));
+                    let hint = u31_mul_hint::<R::M>(a, b);
+                    // the hint goes below a and b
+                    script! {
+                        for x in hint.iter() {
+                            { *x }
+                        }
+                        { U31_MUL_HINT_LEN + 1 } OP_ROLL
+                        { U31_MUL_HINT_LEN + 1 } OP_ROLL
+                        { u31_mul_hinted::<R::M>() }
+                    }
+                }
+                5 if len >= 2 => {
+                    let n = prng.gen_range(1..len);
+                    let x = stack.pop().unwrap();
+                    let ys = stack.split_off(len - 1 - n);
+                    stack.extend(ys.iter().map(|y| R::mul(*y, x)));
+                    u31_mul_many::<R::M>(n)
+                }
+                6 if stack[len - len.min(3)..].iter().all(|x| *x != 0) => {
+                    let n = len.min(3);
+                    let a = stack.split_off(len - n);
+                    let product = a.iter().fold(1, |acc, x| R::mul(acc, *x));
+                    stack.extend(a.iter().map(|x| R::inv(*x)));
+                    // the inverse of the product goes below a_0, ..., a_{n-1}
+                    script! {
+                        { R::inv(product) }
+                        for _ in 0..n {
+                            { n } OP_ROLL
+                        }
+                        { u31_batch_inv::<R::M>(n) }
+                    }
+                }
+                // u31_neg leaves MOD rather than 0 for 0, which u31_add takes on either side and
+                // u31_sub as the subtrahend
+                7 if len >= 2 => {
+                    let a = stack.pop().unwrap();
+                    let b = stack.pop().unwrap();
+                    stack.push(R::sub(b, a));
+                    script! { { u31_neg::<R::M>() } { u31_add::<R::M>() } }
+                }
+                8 if len >= 2 => {
+                    let a = stack.pop().unwrap();
+                    let b = stack.pop().unwrap();
+                    stack.push(R::add(b, a));
+                    script! { { u31_neg::<R::M>() } { u31_sub::<R::M>() } }
+                }
+                9 if len >= 2 => {
+                    let a = stack.pop().unwrap();
+                    let b = stack.pop().unwrap();
+                    stack.push(R::sub(a, b));
+                    script! { OP_SWAP { u31_neg::<R::M>() } { u31_add::<R::M>() } }
+                }
+                10 => {
+                    let a = stack.pop().unwrap();
+                    stack.push(R::add(a, a));
+                    u31_double::<R::M>()
+                }
+                11 => {
+                    let constant = random_value::<R::M>(&mut prng);
+                    let a = stack.pop().unwrap();
+                    stack.push(R::mul(a, constant));
+                    u31_mul_by_constant::<R::M>(constant)
+                }
+                12 => script! { { u31_to_bits() } { u31_from_bits(31) } },
+                13 => script! { { u31_to_nibbles() } { u31_from_nibbles() } },
+                14 => script! { { u31_to_u32_limbs() } { u31_from_u32_limbs::<R::M>() } },
+                15 if len < 6 => {
+                    let x = [u32::MAX, R::M::MOD, prng.gen()][prng.gen_range(0..3)];
+                    stack.push(x % R::M::MOD);
+                    script! { { x >> 16 } { x & 0xffff } { u32_reduce::<R::M>() } }
+                }
+                16 if len < 6 => {
+                    let x = [u64::MAX, prng.gen()][prng.gen_range(0..2)];
+                    stack.push((x % R::M::MOD as u64) as u32);
+                    script! {
+                        for i in (0..4).rev() {
+                            { ((x >> (16 * i)) & 0xffff) as u32 }
+                        }
+                        { u64_reduce::<R::M>() }
+                    }
+                }
+                17 if len >= 2 => {
+                    // a random chain of additions and subtractions of the top n elements,
+                    // from the top one down
+                    let n = prng.gen_range(2..=len);
+                    let values = stack.split_off(len - n);
+                    let (mut dual, mut inputs) = DualScript::<R::M>::new(n);
+                    let b = inputs.pop().unwrap();
+                    let a = inputs.pop().unwrap();
+                    let (mut acc, mut value) = if prng.gen() {
+                        (dual.add(a, b), R::add(values[n - 2], values[n - 1]))
+                    } else {
+                        (dual.sub(a, b), R::sub(values[n - 2], values[n - 1]))
+                    };
+                    for (x, v) in inputs.into_iter().zip(values.iter()).rev() {
+                        if prng.gen() {
+                            acc = dual.add(x, acc);
+                            value = R::add(*v, value);
+                        } else {
+                            acc = dual.sub(x, acc);
+                            value = R::sub(*v, value);
+                        }
+                    }
+                    stack.push(value);
+                    let out = dual.convert::<U31, _>(acc);
+                    dual.finish(vec![out])
+                }
+                18 if len < 6 => {
+                    let depth = prng.gen_range(0..len);
+                    stack.push(stack[len - 1 - depth]);
+                    script! { { depth } OP_PICK }
+                }
+                _ => {
+                    let depth = prng.gen_range(0..len);
+                    let a = stack.remove(len - 1 - depth);
+                    stack.push(a);
+                    script! { { depth } OP_ROLL }
+                }
+            };
+            scripts.push(script);
+        }
+
+        let script = script! {
+            for x in inputs.iter() {
+                { *x }
+            }
+            for script in scripts.iter() {
+                { script.clone() }
+            }
+            for x in stack.iter().rev() {
+                { *x } OP_EQUALVERIFY
+            }
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success, "sequence {} of seed {}", i, seed);
+    }
+}
+
+// u31_neg leaves MOD rather than 0 for 0, which u31_add accepts as an input
+fn fuzz_u31_neg<R: BaseReference>(seed: u64) {
+    let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+    for _ in 0..sequences() {
+        let a = random_value::<R::M>(&mut prng);
+        let b = random_value::<R::M>(&mut prng);
+        let neg = if a == 0 { R::M::MOD } else { R::sub(0, a) };
+
+        let script = script! {
+            { a }
+            { u31_neg::<R::M>() }
+            OP_DUP
+            { neg } OP_EQUALVERIFY
+            { b }
+            { u31_add::<R::M>() }
+            { R::sub(b, a) } OP_EQUAL
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+}
+
+fn push_ext(a: &[u32]) -> Script {
+    script! {
+        for x in a.iter().rev() {
+            { *x }
+        }
+    }
+}
+
+fn fuzz_u31ext<R: ExtReference>(seed: u64) {
+    let mut prng = ChaCha20Rng::seed_from_u64(seed);
+    let degree = R::C::DEGREE as usize;
+
+    for i in 0..sequences() {
+        let inputs: Vec<Vec<u32>> = (0..3)
+            .map(|_| {
+                (0..degree)
+                    .map(|_| random_value::<<R::C as U31ExtConfig>::BaseFieldConfig>(&mut prng))
+                    .collect()
+            })
+            .collect();
+        let mut stack = inputs.clone();
+        let mut scripts = vec![];
+
+        for _ in 0..SEQUENCE_LENGTH {
+            let len = stack.len();
+            let script = match prng.gen_range(0..10) {
+                0 if len >= 2 => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(R::add(&a, &b));
+                    u31ext_add::<R::C>()
+                }
+                1 if len >= 2 => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(R::sub(&a, &b));
+                    u31ext_sub::<R::C>()
+                }
+                2 if len >= 2 => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(R::mul(&a, &b));
+                    u31ext_mul::<R::C>()
+                }
+                3 => {
+                    let b = random_value::<<R::C as U31ExtConfig>::BaseFieldConfig>(&mut prng);
+                    let a = stack.pop().unwrap();
+                    stack.push(R::mul_base(&a, b));
+                    script! { { b } { u31ext_mul_u31::<R::C>() } }
+                }
+                4 => {
+                    let constant =
+                        random_value::<<R::C as U31ExtConfig>::BaseFieldConfig>(&mut prng);
+                    let a = stack.pop().unwrap();
+                    stack.push(R::mul_base(&a, constant));
+                    u31ext_mul_u31_by_constant::<R::C>(constant)
+                }
+                5 => {
+                    let a = stack.pop().unwrap();
+                    stack.push(R::add(&a, &a));
+                    u31ext_double::<R::C>()
+                }
+                6 => {
+                    let n = prng.gen_range(1..=len);
+                    let b = random_value::<<R::C as U31ExtConfig>::BaseFieldConfig>(&mut prng);
+                    let a = stack.split_off(len - n);
+                    stack.extend(a.iter().map(|x| R::mul_base(x, b)));
+                    script! { { b } { u31ext_many_mul_u31::<R::C>(n) } }
+                }
+                7 if stack[len - len.min(3)..]
+                    .iter()
+                    .all(|x| x.iter().any(|limb| *limb != 0)) =>
+                {
+                    let n = len.min(3);
+                    let a = stack.split_off(len - n);
+                    let product = a[1..].iter().fold(a[0].clone(), |acc, x| R::mul(&acc, x));
+                    stack.extend(a.iter().map(|x| R::inv(x)));
+                    // the inverse of the product goes below a_0, ..., a_{n-1}
+                    script! {
+                        { push_ext(&R::inv(&product)) }
+                        for _ in 0..n {
+                            { u31ext_roll::<R::C>(n) }
+                        }
+                        { u31ext_batch_inv::<R::C>(n) }
+                    }
+                }
+                8 if len < 5 => {
+                    let offset = prng.gen_range(0..len);
+                    stack.push(stack[len - 1 - offset].clone());
+                    u31ext_copy::<R::C>(offset)
+                }
+                _ => {
+                    let offset = prng.gen_range(0..len);
+                    let a = stack.remove(len - 1 - offset);
+                    stack.push(a);
+                    u31ext_roll::<R::C>(offset)
+                }
+            };
+            scripts.push(script);
+        }
+
+        let script = script! {
+            for x in inputs.iter() {
+                { push_ext(x) }
+            }
+            for script in scripts.iter() {
+                { script.clone() }
+            }
+            for x in stack.iter().rev() {
+                { push_ext(x) }
+                { u31ext_equalverify::<R::C>() }
+            }
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success, "sequence {} of seed {}", i, seed);
+    }
+}
+
+#[test]
+fn fuzz_m31() {
+    fuzz_u31::<Plonky3M31>(0u64);
+    fuzz_u31_neg::<Plonky3M31>(1u64);
+}
+
+#[test]
+fn fuzz_babybear() {
+    fuzz_u31::<RiscZeroBabyBear>(2u64);
+    fuzz_u31_neg::<RiscZeroBabyBear>(3u64);
+}
+
+#[test]
+fn fuzz_qm31() {
+    fuzz_u31ext::<Plonky3QM31>(4u64);
+}
+
+#[test]
+fn fuzz_babybear4() {
+    fuzz_u31ext::<RiscZeroBabyBear4>(5u64);
+}
@@ -0,0 +1,164 @@
+use crate::U31Config;
+use crate::U31ExtConfig;
+use bitcoin::hashes::{sha256, Hash};
+use bitvm::treepp::*;
+
+// Input: a (a u31 element)
+// Output: the 4-byte little-endian encoding of a
+//
+// A script number in [0, 2^31) is minimally encoded as its little-endian bytes without the
+// trailing zero bytes (with at most one 0x00 sign byte), so padding it with zero bytes up
+// to 4 bytes gives the canonical form.
+pub fn u31_to_le_bytes<M: U31Config>() -> Script {
+    script! {
+        OP_DUP 0 { M::MOD } OP_WITHIN OP_VERIFY
+        OP_SIZE
+        OP_DUP 3 OP_EQUAL
+        OP_IF
+            OP_DROP { vec![0u8; 1] } OP_CAT
+        OP_ELSE
+            OP_DUP 2 OP_EQUAL
+            OP_IF
+                OP_DROP { vec![0u8; 2] } OP_CAT
+            OP_ELSE
+                OP_DUP 1 OP_EQUAL
+                OP_IF
+                    OP_DROP { vec![0u8; 3] } OP_CAT
+                OP_ELSE
+                    OP_NOTIF { vec![0u8; 4] } OP_CAT OP_ENDIF
+                OP_ENDIF
+            OP_ENDIF
+        OP_ENDIF
+    }
+}
+
+// Input: v[n - 1], ..., v[1], v[0]
+// Output: SHA256(le_bytes(v[0]) || le_bytes(v[1]) || ... || le_bytes(v[n - 1]))
+pub fn u31_vec_hash<M: U31Config>(n: u32) -> Script {
+    assert!(
+        (1..=130).contains(&n),
+        "the serialized vector must fit in a 520-byte stack element"
+    );
+
+    script! {
+        { u31_to_le_bytes::<M>() }
+        for _ in 1..n {
+            OP_SWAP
+            { u31_to_le_bytes::<M>() }
+            OP_CAT
+        }
+        OP_SHA256
+    }
+}
+
+pub fn u31ext_hash<C: U31ExtConfig>() -> Script {
+    u31_vec_hash::<C::BaseFieldConfig>(C::DEGREE)
+}
+
+pub fn u31_vec_hash_native(v: &[u32]) -> [u8; 32] {
+    let mut bytes = vec![];
+    for x in v.iter() {
+        bytes.extend_from_slice(&x.to_le_bytes());
+    }
+    sha256::Hash::hash(&bytes).to_byte_array()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        u31_to_le_bytes, u31_vec_hash, u31_vec_hash_native, u31ext_hash, BabyBear, BabyBear4,
+        U31Config, M31, QM31,
+    };
+    use bitvm::treepp::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_u31_to_le_bytes() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+
+        let mut values = vec![0, 1, 127, 128, 255, 256, 32767, 32768, 1 << 23, 1 << 30];
+        values.push(M31::MOD - 1);
+        for _ in 0..100 {
+            values.push(prng.gen::<u32>() % M31::MOD);
+        }
+
+        for a in values {
+            let script = script! {
+                { a }
+                { u31_to_le_bytes::<M31>() }
+                { a.to_le_bytes().to_vec() }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        let script = script! {
+            { M31::MOD }
+            { u31_to_le_bytes::<M31>() }
+            OP_DROP
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+
+    #[test]
+    fn test_u31_vec_hash() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!(
+            "u31 vec hash (8 elements): {}",
+            u31_vec_hash::<BabyBear>(8).len()
+        );
+
+        for n in 1..10 {
+            let v: Vec<u32> = (0..n).map(|_| prng.gen::<u32>() % BabyBear::MOD).collect();
+            let hash = u31_vec_hash_native(&v);
+
+            let script = script! {
+                for x in v.iter().rev() {
+                    { *x }
+                }
+                { u31_vec_hash::<BabyBear>(n) }
+                { hash.to_vec() }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_u31ext_hash() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!("qm31 hash: {}", u31ext_hash::<QM31>().len());
+        eprintln!("babybear4 hash: {}", u31ext_hash::<BabyBear4>().len());
+
+        for _ in 0..10 {
+            let a: Vec<u32> = (0..4).map(|_| prng.gen::<u32>() % M31::MOD).collect();
+            let hash = u31_vec_hash_native(&a);
+
+            let script = script! {
+                { a[3] } { a[2] } { a[1] } { a[0] }
+                { u31ext_hash::<QM31>() }
+                { hash.to_vec() }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            let b: Vec<u32> = (0..4).map(|_| prng.gen::<u32>() % BabyBear::MOD).collect();
+            let hash = u31_vec_hash_native(&b);
+
+            let script = script! {
+                { b[3] } { b[2] } { b[1] } { b[0] }
+                { u31ext_hash::<BabyBear4>() }
+                { hash.to_vec() }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+}
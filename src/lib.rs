@@ -4,6 +4,57 @@ pub use u31::*;
 mod u31_ext;
 pub use u31_ext::*;
 
+mod hash;
+pub use hash::*;
+
+mod merkle;
+pub use merkle::*;
+
+mod nibbles;
+pub use nibbles::*;
+
+mod commit;
+pub use commit::*;
+
+mod u32_limbs;
+pub use u32_limbs::*;
+
+mod field_script;
+pub use field_script::*;
+
+mod builder;
+pub use builder::*;
+
+mod stack_usage;
+pub use stack_usage::*;
+
+mod costs;
+pub use costs::*;
+
+mod chunker;
+pub use chunker::*;
+
+mod peephole;
+pub use peephole::*;
+
+mod dual;
+pub use dual::*;
+
+#[cfg(test)]
+mod fuzz;
+
+mod poseidon2;
+pub use poseidon2::*;
+
+mod deep;
+pub use deep::*;
+
+mod circle;
+pub use circle::*;
+
+mod vanishing;
+pub use vanishing::*;
+
 pub fn unroll<F, T>(count: u32, mut closure: F) -> Vec<T>
 where
     F: FnMut(u32) -> T,
@@ -0,0 +1,297 @@
+use crate::{u31ext_hash, U31ExtConfig};
+use bitcoin::hashes::{sha256, Hash};
+use bitvm::treepp::*;
+
+#[cfg(feature = "blake3")]
+use crate::u31_to_u32_limbs;
+#[cfg(feature = "blake3")]
+use bitvm::hash::blake3::blake3_var_length;
+
+// Input:
+//      root
+//      sibling[depth - 1], bit[depth - 1]
+//      ...
+//      sibling[0], bit[0]
+//      leaf (u31ext)
+//
+// bit[i] is the i-th bit (from the leaf level) of the leaf index, i.e., 1 if the node being
+// authenticated at level i is a right child. The script fails unless the path leads to root.
+pub fn merkle_verify_path<C: U31ExtConfig>(depth: usize) -> Script {
+    script! {
+        { u31ext_hash::<C>() }
+        for _ in 0..depth {
+            { merkle_hash_node() }
+        }
+        OP_EQUALVERIFY
+    }
+}
+
+// Input: sibling, bit, node
+// Output: SHA256(node || sibling) if bit is 0, SHA256(sibling || node) otherwise
+pub fn merkle_hash_node() -> Script {
+    script! {
+        OP_ROT OP_ROT
+        OP_IF OP_SWAP OP_ENDIF
+        OP_CAT
+        OP_SHA256
+    }
+}
+
+pub fn merkle_hash_node_native(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut bytes = left.to_vec();
+    bytes.extend_from_slice(right);
+    sha256::Hash::hash(&bytes).to_byte_array()
+}
+
+// Same as merkle_verify_path with BitVM's BLAKE3, which needs no OP_CAT. A hash is 32 stack
+// elements, one per byte, with the first byte at the bottom.
+//
+// Input:
+//      root (32 bytes)
+//      sibling[depth - 1] (32 bytes), bit[depth - 1]
+//      ...
+//      sibling[0] (32 bytes), bit[0]
+//      leaf (u31ext)
+#[cfg(feature = "blake3")]
+pub fn merkle_verify_path_blake3<C: U31ExtConfig>(depth: usize) -> Script {
+    script! {
+        { merkle_hash_leaf_blake3::<C>() }
+        for _ in 0..depth {
+            { merkle_hash_node_blake3() }
+        }
+        for i in (1..=32).rev() {
+            { i } OP_ROLL
+            OP_EQUALVERIFY
+        }
+    }
+}
+
+// Input: a (u31ext)
+// Output: BLAKE3(le_bytes(a[0]) || le_bytes(a[1]) || ... || le_bytes(a[DEGREE - 1]))
+#[cfg(feature = "blake3")]
+pub fn merkle_hash_leaf_blake3<C: U31ExtConfig>() -> Script {
+    let degree = C::DEGREE as usize;
+
+    script! {
+        { u31_to_le_byte_elements() }
+        for i in 1..degree {
+            { 4 * i } OP_ROLL
+            { u31_to_le_byte_elements() }
+        }
+        { blake3_var_length(4 * degree) }
+    }
+}
+
+// Input: sibling (32 bytes), bit, node (32 bytes)
+// Output: BLAKE3(node || sibling) if bit is 0, BLAKE3(sibling || node) otherwise
+#[cfg(feature = "blake3")]
+pub fn merkle_hash_node_blake3() -> Script {
+    script! {
+        32 OP_ROLL
+        OP_NOTIF
+            for _ in 0..32 {
+                63 OP_ROLL
+            }
+        OP_ENDIF
+        { blake3_var_length(64) }
+    }
+}
+
+// Input: a
+// Output: a & 255, (a >> 8) & 255, (a >> 16) & 255, (a >> 24) & 255
+#[cfg(feature = "blake3")]
+fn u31_to_le_byte_elements() -> Script {
+    script! {
+        { u31_to_u32_limbs() }
+        OP_SWAP OP_2SWAP OP_SWAP
+    }
+}
+
+#[cfg(feature = "blake3")]
+pub fn merkle_hash_leaf_blake3_native(leaf: &[u32]) -> [u8; 32] {
+    let mut bytes = vec![];
+    for x in leaf.iter() {
+        bytes.extend_from_slice(&x.to_le_bytes());
+    }
+    *blake3::hash(&bytes).as_bytes()
+}
+
+#[cfg(feature = "blake3")]
+pub fn merkle_hash_node_blake3_native(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut bytes = left.to_vec();
+    bytes.extend_from_slice(right);
+    *blake3::hash(&bytes).as_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        merkle_hash_node_native, merkle_verify_path, u31_vec_hash_native, BabyBear4, U31Config,
+        U31ExtConfig, QM31,
+    };
+    use bitvm::treepp::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    const DEPTH: usize = 5;
+
+    type HashLeaf = fn(&[u32]) -> [u8; 32];
+    type HashNode = fn(&[u8; 32], &[u8; 32]) -> [u8; 32];
+
+    struct MerkleTree {
+        layers: Vec<Vec<[u8; 32]>>,
+    }
+
+    impl MerkleTree {
+        fn new(leaves: &[Vec<u32>], hash_leaf: HashLeaf, hash_node: HashNode) -> Self {
+            let mut layers = vec![leaves
+                .iter()
+                .map(|leaf| hash_leaf(leaf))
+                .collect::<Vec<_>>()];
+            while layers.last().unwrap().len() > 1 {
+                let next = layers
+                    .last()
+                    .unwrap()
+                    .chunks(2)
+                    .map(|pair| hash_node(&pair[0], &pair[1]))
+                    .collect();
+                layers.push(next);
+            }
+            Self { layers }
+        }
+
+        fn root(&self) -> [u8; 32] {
+            self.layers.last().unwrap()[0]
+        }
+
+        fn path(&self, mut index: usize) -> Vec<([u8; 32], usize)> {
+            let mut path = vec![];
+            for layer in self.layers.iter().take(self.layers.len() - 1) {
+                path.push((layer[index ^ 1], index & 1));
+                index >>= 1;
+            }
+            path
+        }
+    }
+
+    // verify is the verification script for paths of depth DEPTH
+    fn test_merkle_verify_path_generic<C: U31ExtConfig>(
+        seed: u64,
+        hash_leaf: HashLeaf,
+        hash_node: HashNode,
+        push_hash: fn(&[u8; 32]) -> Script,
+        verify: Script,
+    ) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+        let depth = DEPTH;
+
+        let leaves: Vec<Vec<u32>> = (0..1 << depth)
+            .map(|_| {
+                (0..C::DEGREE)
+                    .map(|_| prng.gen::<u32>() % <C::BaseFieldConfig as U31Config>::MOD)
+                    .collect()
+            })
+            .collect();
+        let tree = MerkleTree::new(&leaves, hash_leaf, hash_node);
+
+        for _ in 0..10 {
+            let index = prng.gen_range(0..1 << depth);
+            let path = tree.path(index);
+            let leaf = &leaves[index];
+
+            let script = script! {
+                { push_hash(&tree.root()) }
+                for (sibling, bit) in path.iter().rev() {
+                    { push_hash(sibling) }
+                    { *bit }
+                }
+                for x in leaf.iter().rev() {
+                    { *x }
+                }
+                { verify.clone() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            // flipping the lowest bit of the index must fail the verification
+            let mut wrong_path = path.clone();
+            wrong_path[0].1 ^= 1;
+
+            let script = script! {
+                { push_hash(&tree.root()) }
+                for (sibling, bit) in wrong_path.iter().rev() {
+                    { push_hash(sibling) }
+                    { *bit }
+                }
+                for x in leaf.iter().rev() {
+                    { *x }
+                }
+                { verify.clone() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(!exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_merkle_verify_path() {
+        eprintln!(
+            "qm31 merkle path (depth 20): {}",
+            merkle_verify_path::<QM31>(20).len()
+        );
+
+        let push_hash = |hash: &[u8; 32]| script! { { hash.to_vec() } };
+        test_merkle_verify_path_generic::<QM31>(
+            0u64,
+            u31_vec_hash_native,
+            merkle_hash_node_native,
+            push_hash,
+            merkle_verify_path::<QM31>(DEPTH),
+        );
+        test_merkle_verify_path_generic::<BabyBear4>(
+            1u64,
+            u31_vec_hash_native,
+            merkle_hash_node_native,
+            push_hash,
+            merkle_verify_path::<BabyBear4>(DEPTH),
+        );
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_merkle_verify_path_blake3() {
+        use crate::{
+            merkle_hash_leaf_blake3_native, merkle_hash_node_blake3_native,
+            merkle_verify_path_blake3,
+        };
+
+        eprintln!(
+            "qm31 blake3 merkle path (depth 20): {}",
+            merkle_verify_path_blake3::<QM31>(20).len()
+        );
+
+        let push_hash = |hash: &[u8; 32]| {
+            script! {
+                for byte in hash.iter() {
+                    { *byte as u32 }
+                }
+            }
+        };
+        test_merkle_verify_path_generic::<QM31>(
+            2u64,
+            merkle_hash_leaf_blake3_native,
+            merkle_hash_node_blake3_native,
+            push_hash,
+            merkle_verify_path_blake3::<QM31>(DEPTH),
+        );
+        test_merkle_verify_path_generic::<BabyBear4>(
+            3u64,
+            merkle_hash_leaf_blake3_native,
+            merkle_hash_node_blake3_native,
+            push_hash,
+            merkle_verify_path_blake3::<BabyBear4>(DEPTH),
+        );
+    }
+}
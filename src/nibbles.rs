@@ -0,0 +1,196 @@
+use crate::{u31::u31_to_limbs, unroll, U31ExtConfig};
+use bitvm::treepp::*;
+
+// Winternitz signatures in BitVM (`bitvm::signatures::winternitz`) sign a message given as
+// 4-bit digits, where digits 2i and 2i + 1 are the low and high nibble of the i-th message
+// byte. A u31 element is committed to as the 8 nibbles of its little-endian encoding, i.e.,
+// nibble[i] = (a >> 4i) & 15, with nibble[0] coming first.
+
+// Input: a
+// Output: nibble[7], ..., nibble[1], nibble[0]
+pub fn u31_to_nibbles() -> Script {
+    u31_to_limbs(4)
+}
+
+// Input: nibble[7], ..., nibble[1], nibble[0]
+// Output: a = sum nibble[i] * 16^i
+//
+// The nibbles must be in [0, 16), and nibble[7] in [0, 8), as guaranteed by a Winternitz
+// signature check. Whether a is canonical is left to the caller.
+pub fn u31_from_nibbles() -> Script {
+    script! {
+        7 OP_ROLL
+        { unroll(7, |i| script! {
+            OP_DUP OP_ADD OP_DUP OP_ADD OP_DUP OP_ADD OP_DUP OP_ADD
+            { 7 - i } OP_ROLL OP_ADD
+        }) }
+    }
+}
+
+// Input: a (u31ext)
+// Output: the nibbles of a[DEGREE - 1], ..., the nibbles of a[0]
+pub fn u31ext_to_nibbles<C: U31ExtConfig>() -> Script {
+    script! {
+        for i in 0..C::DEGREE {
+            { 8 * i + C::DEGREE - 1 - i } OP_ROLL
+            { u31_to_nibbles() }
+        }
+    }
+}
+
+pub fn u31_to_nibbles_native(a: u32) -> [u8; 8] {
+    let mut nibbles = [0u8; 8];
+    for (i, nibble) in nibbles.iter_mut().enumerate() {
+        *nibble = ((a >> (4 * i)) & 15) as u8;
+    }
+    nibbles
+}
+
+pub fn u31ext_to_nibbles_native(a: &[u32]) -> Vec<u8> {
+    a.iter().flat_map(|x| u31_to_nibbles_native(*x)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        u31_from_nibbles, u31_to_nibbles, u31_to_nibbles_native, u31ext_to_nibbles,
+        u31ext_to_nibbles_native, BabyBear, BabyBear4, U31Config, U31ExtConfig, M31, QM31,
+    };
+    use bitvm::signatures::winternitz::{checksig_verify, generate_public_key, sign_digits};
+    use bitvm::treepp::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    const SECRET_KEY: &str = "b138982ce17ac813d505b5b40b665d404e9528e7";
+
+    #[test]
+    fn test_u31_to_nibbles() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!("u31 to nibbles: {}", u31_to_nibbles().len());
+        eprintln!("u31 from nibbles: {}", u31_from_nibbles().len());
+
+        let mut values = vec![0, 1, 15, 16, (1 << 31) - 1, BabyBear::MOD - 1];
+        for _ in 0..100 {
+            values.push(prng.gen::<u32>() % M31::MOD);
+        }
+
+        for a in values {
+            let nibbles = u31_to_nibbles_native(a);
+
+            let script = script! {
+                { a }
+                { u31_to_nibbles() }
+                for nibble in nibbles.iter() {
+                    { *nibble as u32 }
+                    OP_EQUALVERIFY
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            let script = script! {
+                { a }
+                { u31_to_nibbles() }
+                { u31_from_nibbles() }
+                { a }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_u31_to_nibbles_winternitz() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        let public_key = generate_public_key(SECRET_KEY);
+
+        for _ in 0..10 {
+            let a = prng.gen::<u32>() % M31::MOD;
+
+            // sign a 20-byte message whose first four bytes encode a
+            let mut digits = [0u8; 40];
+            digits[..8].copy_from_slice(&u31_to_nibbles_native(a));
+
+            // checksig_verify leaves the message bytes with the first one on top, which are
+            // matched against the nibbles computed in the script
+            let script = script! {
+                { sign_digits(SECRET_KEY, digits) }
+                { checksig_verify(&public_key) }
+                { a }
+                { u31_to_nibbles() }
+                for k in 0..4 {
+                    OP_SWAP
+                    OP_DUP OP_ADD OP_DUP OP_ADD OP_DUP OP_ADD OP_DUP OP_ADD
+                    OP_ADD
+                    { 7 - 2 * k } OP_ROLL
+                    OP_EQUALVERIFY
+                }
+                for _ in 0..16 {
+                    OP_NOT OP_VERIFY
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            // a signature on different nibbles must not match
+            let mut wrong_digits = digits;
+            wrong_digits[0] ^= 1;
+
+            let script = script! {
+                { sign_digits(SECRET_KEY, wrong_digits) }
+                { checksig_verify(&public_key) }
+                { a }
+                { u31_to_nibbles() }
+                for k in 0..4 {
+                    OP_SWAP
+                    OP_DUP OP_ADD OP_DUP OP_ADD OP_DUP OP_ADD OP_DUP OP_ADD
+                    OP_ADD
+                    { 7 - 2 * k } OP_ROLL
+                    OP_EQUALVERIFY
+                }
+                for _ in 0..16 {
+                    OP_NOT OP_VERIFY
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(!exec_result.success);
+        }
+    }
+
+    fn test_u31ext_to_nibbles_generic<C: U31ExtConfig>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+        for _ in 0..10 {
+            let a: Vec<u32> = (0..C::DEGREE)
+                .map(|_| prng.gen::<u32>() % <C::BaseFieldConfig as U31Config>::MOD)
+                .collect();
+            let nibbles = u31ext_to_nibbles_native(&a);
+
+            let script = script! {
+                for x in a.iter().rev() {
+                    { *x }
+                }
+                { u31ext_to_nibbles::<C>() }
+                for nibble in nibbles.iter() {
+                    { *nibble as u32 }
+                    OP_EQUALVERIFY
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_u31ext_to_nibbles() {
+        eprintln!("qm31 to nibbles: {}", u31ext_to_nibbles::<QM31>().len());
+
+        test_u31ext_to_nibbles_generic::<QM31>(0u64);
+        test_u31ext_to_nibbles_generic::<BabyBear4>(1u64);
+    }
+}
@@ -0,0 +1,283 @@
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::Opcode;
+use bitvm::treepp::*;
+
+// Removes the no-ops that composing scripts leaves at the boundaries, such as
+// `OP_FROMALTSTACK OP_TOALTSTACK`, `OP_SWAP OP_SWAP`, `{x} OP_SUB {x} OP_ADD` and `0 OP_ROLL`,
+// and fuses `u31_adjust` followed by `u31_to_v31` into `v31_adjust`, which saves 6 bytes
+// between two consecutive `u31_add`.
+//
+// The optimized script leaves the same stack and altstack as the original one whenever the
+// original one succeeds. A script that does not parse is returned unchanged.
+pub fn peephole_optimize(script: &Script) -> Script {
+    let instructions = match split_instructions(script.as_bytes()) {
+        Some(instructions) => instructions,
+        None => return script.clone(),
+    };
+
+    let mut out: Vec<Vec<u8>> = vec![];
+    for instruction in instructions {
+        out.push(instruction);
+        while let Some((len, replacement)) = match_tail(&out) {
+            out.truncate(out.len() - len);
+            out.extend(replacement);
+        }
+    }
+    Script::from(out.concat())
+}
+
+// Splits the bytes of a script into its instructions, pushes included, or returns None if a
+// push runs past the end of the script.
+fn split_instructions(bytes: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut instructions = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let opcode = bytes[i];
+        let (header, len) = if opcode <= OP_PUSHBYTES_75.to_u8() {
+            (1, opcode as usize)
+        } else if opcode == OP_PUSHDATA1.to_u8() {
+            (2, *bytes.get(i + 1)? as usize)
+        } else if opcode == OP_PUSHDATA2.to_u8() {
+            let len = bytes.get(i + 1..i + 3)?.try_into().ok()?;
+            (3, u16::from_le_bytes(len) as usize)
+        } else if opcode == OP_PUSHDATA4.to_u8() {
+            let len = bytes.get(i + 1..i + 5)?.try_into().ok()?;
+            (5, u32::from_le_bytes(len) as usize)
+        } else {
+            (1, 0)
+        };
+        instructions.push(bytes.get(i..i + header + len)?.to_vec());
+        i += header + len;
+    }
+    Some(instructions)
+}
+
+fn op(opcode: Opcode) -> Vec<u8> {
+    vec![opcode.to_u8()]
+}
+
+fn is_push(instruction: &[u8]) -> bool {
+    let opcode = instruction[0];
+    opcode <= OP_PUSHNUM_16.to_u8() && opcode != OP_RESERVED.to_u8()
+}
+
+// Finds a pattern at the end of the instructions, and returns its length and its replacement.
+fn match_tail(out: &[Vec<u8>]) -> Option<(usize, Vec<Vec<u8>>)> {
+    let n = out.len();
+
+    let rules = [
+        (vec![OP_FROMALTSTACK, OP_TOALTSTACK], vec![]),
+        (vec![OP_TOALTSTACK, OP_FROMALTSTACK], vec![]),
+        (vec![OP_SWAP, OP_SWAP], vec![]),
+        (vec![OP_PUSHBYTES_0, OP_ROLL], vec![]),
+        (vec![OP_PUSHBYTES_0, OP_PICK], vec![OP_DUP]),
+        (vec![OP_PUSHNUM_1, OP_ROLL], vec![OP_SWAP]),
+        (vec![OP_PUSHNUM_1, OP_PICK], vec![OP_OVER]),
+    ];
+    for (pattern, replacement) in rules.iter() {
+        let len = pattern.len();
+        if n >= len
+            && out[n - len..]
+                .iter()
+                .zip(pattern.iter())
+                .all(|(a, b)| *a == op(*b))
+        {
+            return Some((len, replacement.iter().map(|b| op(*b)).collect()));
+        }
+    }
+
+    // {x} OP_SUB {x} OP_ADD, e.g. u31_to_v31 followed by v31_to_u31, and the other way around
+    if n >= 4 && out[n - 4] == out[n - 2] && is_push(&out[n - 2]) {
+        let (first, second) = (&out[n - 3], &out[n - 1]);
+        if (*first == op(OP_SUB) && *second == op(OP_ADD))
+            || (*first == op(OP_ADD) && *second == op(OP_SUB))
+        {
+            return Some((4, vec![]));
+        }
+    }
+
+    // u31_adjust followed by u31_to_v31 is v31_adjust
+    if n >= 9 && out[n - 5] == out[n - 2] && is_push(&out[n - 2]) {
+        let u31_adjust_to_v31 = [
+            op(OP_DUP),
+            op(OP_PUSHBYTES_0),
+            op(OP_LESSTHAN),
+            op(OP_IF),
+            out[n - 2].clone(),
+            op(OP_ADD),
+            op(OP_ENDIF),
+            out[n - 2].clone(),
+            op(OP_SUB),
+        ];
+        if out[n - 9..] == u31_adjust_to_v31 {
+            let v31_adjust = vec![
+                op(OP_DUP),
+                op(OP_PUSHBYTES_0),
+                op(OP_GREATERTHANOREQUAL),
+                op(OP_IF),
+                out[n - 2].clone(),
+                op(OP_SUB),
+                op(OP_ENDIF),
+            ];
+            return Some((9, v31_adjust));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        peephole_optimize, u31_to_v31, v31_to_u31, BabyBear, BabyBear4, FieldScript, U31Config,
+        U31ExtConfig, M31, QM31,
+    };
+    use bitcoin::opcodes::all::*;
+    use bitvm::treepp::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn random_input<M: U31Config>(prng: &mut ChaCha20Rng) -> u32 {
+        match prng.gen_range(0..5) {
+            0 => 0,
+            1 => 1,
+            2 => M::MOD - 1,
+            _ => prng.gen_range(0..M::MOD),
+        }
+    }
+
+    // Runs the original and the optimized script on the same random inputs and compares the
+    // outputs.
+    fn check_equivalent<M: U31Config>(prng: &mut ChaCha20Rng, fs: &FieldScript) {
+        assert!(fs.is_balanced());
+        let optimized = peephole_optimize(&fs.script);
+        assert!(optimized.len() <= fs.script.len());
+
+        for _ in 0..10 {
+            let inputs: Vec<u32> = (0..fs.inputs).map(|_| random_input::<M>(prng)).collect();
+            let script = script! {
+                for x in inputs.iter() {
+                    { *x }
+                }
+                { fs.script.clone() }
+                for x in inputs.iter() {
+                    { *x }
+                }
+                { optimized.clone() }
+                for i in 0..fs.outputs {
+                    { fs.outputs - i } OP_ROLL OP_EQUALVERIFY
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_peephole_patterns() {
+        let script = script! {
+            OP_SWAP OP_SWAP
+            OP_TOALTSTACK OP_FROMALTSTACK
+            0 OP_ROLL
+            { u31_to_v31::<M31>() }
+            { v31_to_u31::<M31>() }
+            1 OP_ROLL OP_SWAP
+        };
+        assert_eq!(peephole_optimize(&script).len(), 0);
+
+        let script = script! { 0 OP_PICK 1 OP_PICK OP_ADD };
+        assert_eq!(
+            peephole_optimize(&script).as_bytes(),
+            script! { OP_DUP OP_OVER OP_ADD }.as_bytes()
+        );
+
+        // the pushes must be the same
+        let script = script! { 5 OP_SUB 6 OP_ADD };
+        assert_eq!(peephole_optimize(&script).len(), script.len());
+
+        let fs = FieldScript::u31ext_toaltstack::<QM31>()
+            .then(FieldScript::u31ext_fromaltstack::<QM31>());
+        assert_eq!(peephole_optimize(&fs.script).len(), 0);
+    }
+
+    #[test]
+    fn test_peephole_malformed() {
+        // pushes that run past the end of the script
+        for bytes in [
+            vec![0x05, 0x01],
+            vec![OP_PUSHDATA1.to_u8()],
+            vec![OP_PUSHDATA1.to_u8(), 0x02, 0x01],
+            vec![OP_PUSHDATA2.to_u8(), 0x01],
+            vec![OP_PUSHDATA4.to_u8(), 0x01, 0x00, 0x00],
+            vec![OP_SWAP.to_u8(), OP_SWAP.to_u8(), 0x05, 0x01],
+        ] {
+            let script = Script::from(bytes);
+            assert_eq!(peephole_optimize(&script).as_bytes(), script.as_bytes());
+        }
+    }
+
+    fn test_peephole_u31_generic<M: U31Config>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+        // a + b + c + d
+        let fs = FieldScript::u31_add::<M>()
+            .then(FieldScript::u31_add::<M>())
+            .then(FieldScript::u31_add::<M>());
+        let add_len = FieldScript::u31_add::<M>().script.len();
+        let optimized = peephole_optimize(&fs.script);
+        eprintln!("u31_add x3: {} -> {}", fs.script.len(), optimized.len());
+        assert_eq!(optimized.len(), 3 * add_len - 2 * 6);
+        check_equivalent::<M>(&mut prng, &fs);
+
+        let fs = FieldScript::u31_double::<M>()
+            .then(FieldScript::u31_add::<M>())
+            .then(FieldScript::u31_sub::<M>())
+            .then(FieldScript::u31_neg::<M>())
+            .then(FieldScript::u31_add::<M>())
+            .then(FieldScript::u31_mul::<M>())
+            .then(FieldScript::u31_add::<M>());
+        eprintln!(
+            "u31 chain: {} -> {}",
+            fs.script.len(),
+            peephole_optimize(&fs.script).len()
+        );
+        check_equivalent::<M>(&mut prng, &fs);
+    }
+
+    fn test_peephole_u31ext_generic<C: U31ExtConfig>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+        // (a * b + a) * b, with a kept on the altstack in between
+        let fs = FieldScript::u31ext_copy::<C>(1)
+            .then(FieldScript::u31ext_toaltstack::<C>())
+            .then(FieldScript::u31ext_copy::<C>(0))
+            .then(FieldScript::u31ext_toaltstack::<C>())
+            .then(FieldScript::u31ext_mul::<C>())
+            .then(FieldScript::u31ext_fromaltstack::<C>())
+            .then(FieldScript::u31ext_fromaltstack::<C>())
+            .then(FieldScript::u31ext_roll::<C>(2))
+            .then(FieldScript::u31ext_add::<C>())
+            .then(FieldScript::u31ext_mul::<C>());
+        eprintln!(
+            "u31ext composition: {} -> {}",
+            fs.script.len(),
+            peephole_optimize(&fs.script).len()
+        );
+        check_equivalent::<C::BaseFieldConfig>(&mut prng, &fs);
+
+        let fs = FieldScript::u31ext_add::<C>()
+            .then(FieldScript::u31ext_double::<C>())
+            .then(FieldScript::u31ext_sub::<C>())
+            .then(FieldScript::u31ext_mul_u31::<C>());
+        check_equivalent::<C::BaseFieldConfig>(&mut prng, &fs);
+    }
+
+    #[test]
+    fn test_peephole_equivalence() {
+        test_peephole_u31_generic::<M31>(0u64);
+        test_peephole_u31_generic::<BabyBear>(1u64);
+        test_peephole_u31ext_generic::<QM31>(2u64);
+        test_peephole_u31ext_generic::<BabyBear4>(3u64);
+    }
+}
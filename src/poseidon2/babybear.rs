@@ -0,0 +1,145 @@
+use crate::{u31_mul, BabyBear, Poseidon2Config, POSEIDON2_WIDTH};
+use bitvm::treepp::*;
+
+pub struct Poseidon2BabyBear;
+
+impl Poseidon2Config for Poseidon2BabyBear {
+    type BaseFieldConfig = BabyBear;
+    const ROUNDS_F: usize = 8;
+    const ROUNDS_P: usize = 13;
+
+    // [-2, 1, 2, 1/2, 3, 4, -1/2, -3, -4, 1/2^8, 1/4, 1/8, 1/2^27, -1/2^8, -1/16, -1/2^27]
+    const INTERNAL_DIAG: [u32; POSEIDON2_WIDTH] = [
+        0x77ffffff, 0x1, 0x2, 0x3c000001, 0x3, 0x4, 0x3c000000, 0x77fffffe, 0x77fffffd, 0x77880001,
+        0x5a000001, 0x69000001, 0x77fffff2, 0x780000, 0x7800000, 0xf,
+    ];
+
+    // x^7
+    fn sbox_impl() -> Script {
+        script! {
+            OP_DUP OP_DUP
+            { u31_mul::<BabyBear>() }
+            OP_OVER
+            { u31_mul::<BabyBear>() }
+            OP_DUP
+            { u31_mul::<BabyBear>() }
+            { u31_mul::<BabyBear>() }
+        }
+    }
+}
+
+// The round constants of the Poseidon2 reference implementation for BabyBear with width 16,
+// drawn from its Grain LFSR (see poseidon2::test::grain_round_constants). Of the ROUNDS_F +
+// ROUNDS_P rows it draws, the first 4 and the last 4 are the full rounds, and each partial round
+// takes the first constant of its row.
+pub const POSEIDON2_BABYBEAR_EXTERNAL_CONSTANTS: [[u32; POSEIDON2_WIDTH]; 8] = [
+    [
+        0x69cbb6af, 0x46ad93f9, 0x60a00f4e, 0x6b1297cd, 0x23189afe, 0x732e7bef, 0x72c246de,
+        0x2c941900, 0x0557eede, 0x1580496f, 0x3a3ea77b, 0x54f3f271, 0x0f49b029, 0x47872fe1,
+        0x221e2e36, 0x1ab7202e,
+    ],
+    [
+        0x487779a6, 0x3851c9d8, 0x38dc17c0, 0x209f8849, 0x268dcee8, 0x350c48da, 0x5b9ad32e,
+        0x0523272b, 0x3f89055b, 0x01e894b2, 0x13ddedde, 0x1b2ef334, 0x7507d8b4, 0x6ceeb94e,
+        0x52eb6ba2, 0x50642905,
+    ],
+    [
+        0x05453f3f, 0x06349efc, 0x6922787c, 0x04bfff9c, 0x768c714a, 0x3e9ff21a, 0x15737c9c,
+        0x2229c807, 0x0d47f88c, 0x097e0ecc, 0x27eadba0, 0x2d7d29e4, 0x3502aaa0, 0x0f475fd7,
+        0x29fbda49, 0x018afffd,
+    ],
+    [
+        0x0315b618, 0x6d4497d1, 0x1b171d9e, 0x52861abd, 0x2e5d0501, 0x3ec8646c, 0x6e5f250a,
+        0x148ae8e6, 0x17f5fa4a, 0x3e66d284, 0x0051aa3b, 0x483f7913, 0x2cfe5f15, 0x023427ca,
+        0x2cc78315, 0x1e36ea47,
+    ],
+    [
+        0x366cb7ec, 0x0e6335de, 0x5e1374ca, 0x493cf6d2, 0x2ffe3703, 0x19dd3b51, 0x3d64878f,
+        0x3ef43ee8, 0x64723e7c, 0x4fe5418a, 0x0f7b671d, 0x3f3adb8c, 0x1830fd89, 0x5b15366e,
+        0x3ca9204d, 0x149cee3c,
+    ],
+    [
+        0x547bb959, 0x4d6a44a0, 0x771612ca, 0x3f5bdd26, 0x23a3d984, 0x170b07bd, 0x5a2a5094,
+        0x6e7e68b4, 0x1f3c8320, 0x0ffbb8b6, 0x5ebe7442, 0x45ffc700, 0x64d1f7b6, 0x1b30b661,
+        0x586ea500, 0x503111fd,
+    ],
+    [
+        0x72b41cf7, 0x6468ad65, 0x64c713b1, 0x450b1ccd, 0x211e6028, 0x300b11ac, 0x74226654,
+        0x56308a44, 0x5aa55b4a, 0x52f2bc9a, 0x1a076e50, 0x5eb92894, 0x13baaf6f, 0x4d19b625,
+        0x30d25297, 0x52f00c13,
+    ],
+    [
+        0x2a6753d7, 0x40bdd8de, 0x22acbb98, 0x77e41654, 0x23ab6b0f, 0x0629e7d6, 0x000eadff,
+        0x64cc8e81, 0x364fc012, 0x43cc48cd, 0x611baf29, 0x48bdf828, 0x1a8ab06f, 0x112ee5e0,
+        0x036e01dc, 0x18106634,
+    ],
+];
+
+pub const POSEIDON2_BABYBEAR_INTERNAL_CONSTANTS: [u32; 13] = [
+    0x5a8053c0, 0x76a859a0, 0x1448bc54, 0x0eba33ba, 0x1d7c2824, 0x1cb929e6, 0x16dd2e49, 0x0d8eacbc,
+    0x27c99e66, 0x4b1392b6, 0x02d04b6d, 0x1d7cd264, 0x0f8b2954,
+];
+
+#[cfg(test)]
+mod test {
+    use crate::poseidon2::test::{
+        test_poseidon2_constants_generic, test_poseidon2_external_layer_generic,
+        test_poseidon2_internal_layer_generic, test_poseidon2_known_answer_generic,
+        test_poseidon2_permute_generic,
+    };
+    use crate::{
+        poseidon2_external_layer, poseidon2_internal_layer, Poseidon2BabyBear,
+        POSEIDON2_BABYBEAR_EXTERNAL_CONSTANTS, POSEIDON2_BABYBEAR_INTERNAL_CONSTANTS,
+    };
+    use p3_baby_bear::BabyBear as P3Field;
+    use p3_baby_bear::DiffusionMatrixBabyBear;
+
+    #[test]
+    fn test_poseidon2_internal_layer() {
+        eprintln!(
+            "babybear poseidon2 internal layer: {}",
+            poseidon2_internal_layer::<Poseidon2BabyBear>().len()
+        );
+        test_poseidon2_internal_layer_generic::<Poseidon2BabyBear, P3Field, _>(
+            DiffusionMatrixBabyBear,
+        );
+    }
+
+    #[test]
+    fn test_poseidon2_external_layer() {
+        eprintln!(
+            "babybear poseidon2 external layer: {}",
+            poseidon2_external_layer::<Poseidon2BabyBear>().len()
+        );
+        test_poseidon2_external_layer_generic::<Poseidon2BabyBear, P3Field>();
+    }
+
+    #[test]
+    fn test_poseidon2_permute() {
+        test_poseidon2_permute_generic::<Poseidon2BabyBear, P3Field, _, 7>(DiffusionMatrixBabyBear);
+    }
+
+    #[test]
+    fn test_poseidon2_constants() {
+        test_poseidon2_constants_generic::<Poseidon2BabyBear>(
+            &POSEIDON2_BABYBEAR_EXTERNAL_CONSTANTS,
+            &POSEIDON2_BABYBEAR_INTERNAL_CONSTANTS,
+        );
+    }
+
+    #[test]
+    fn test_poseidon2_known_answer() {
+        // the permutation of 0, 1, ..., 15
+        let expected = [
+            1952993082, 1617884793, 90683999, 1056283110, 867545409, 290768337, 1606559591,
+            1225374373, 1789096927, 494560864, 1094240052, 1575300684, 540591577, 1767075193,
+            341504408, 1747000221,
+        ];
+        test_poseidon2_known_answer_generic::<Poseidon2BabyBear, P3Field, _, 7>(
+            DiffusionMatrixBabyBear,
+            &POSEIDON2_BABYBEAR_EXTERNAL_CONSTANTS,
+            &POSEIDON2_BABYBEAR_INTERNAL_CONSTANTS,
+            expected,
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{u31_mul, Poseidon2Config, M31, POSEIDON2_WIDTH};
+use bitvm::treepp::*;
+
+pub struct Poseidon2M31;
+
+impl Poseidon2Config for Poseidon2M31 {
+    type BaseFieldConfig = M31;
+    const ROUNDS_F: usize = 8;
+    const ROUNDS_P: usize = 14;
+
+    // [-2, 2^0, 2^1, 2^2, 2^3, 2^4, 2^5, 2^6, 2^7, 2^8, 2^10, 2^12, 2^13, 2^14, 2^15, 2^16]
+    const INTERNAL_DIAG: [u32; POSEIDON2_WIDTH] = [
+        0x7ffffffd,
+        1 << 0,
+        1 << 1,
+        1 << 2,
+        1 << 3,
+        1 << 4,
+        1 << 5,
+        1 << 6,
+        1 << 7,
+        1 << 8,
+        1 << 10,
+        1 << 12,
+        1 << 13,
+        1 << 14,
+        1 << 15,
+        1 << 16,
+    ];
+
+    // x^5
+    fn sbox_impl() -> Script {
+        script! {
+            OP_DUP OP_DUP
+            { u31_mul::<M31>() }
+            OP_DUP
+            { u31_mul::<M31>() }
+            { u31_mul::<M31>() }
+        }
+    }
+}
+
+// The round constants of the Poseidon2 reference implementation for Mersenne31 with width 16,
+// drawn from the same Grain LFSR as the BabyBear ones, with 14 partial rounds.
+pub const POSEIDON2_M31_EXTERNAL_CONSTANTS: [[u32; POSEIDON2_WIDTH]; 8] = [
+    [
+        0x768bab52, 0x70e0ab7d, 0x3d266c8a, 0x6da42045, 0x600fef22, 0x41dace6b, 0x64f9bdd4,
+        0x5d42d4fe, 0x76b1516d, 0x6fc9a717, 0x70ac4fb6, 0x00194ef6, 0x22b644e2, 0x1f7916d5,
+        0x47581be2, 0x2710a123,
+    ],
+    [
+        0x6284e867, 0x018d3afe, 0x5df99ef3, 0x4c1e467b, 0x566f6abc, 0x2994e427, 0x538a6d42,
+        0x5d7bf2cf, 0x7fda2dab, 0x0fd854c4, 0x46922fca, 0x3d7763a1, 0x19fd05ca, 0x0a4bbb43,
+        0x15075851, 0x3d903d76,
+    ],
+    [
+        0x2d290ff7, 0x40809fa0, 0x59dac6ec, 0x127927a2, 0x6bbf0ea0, 0x0294140f, 0x24742976,
+        0x6e84c081, 0x22484f4a, 0x354cae59, 0x0453ffe1, 0x3f47a3cc, 0x0088204e, 0x6066e109,
+        0x3b7c4b80, 0x6b55665d,
+    ],
+    [
+        0x3bc4b897, 0x735bf378, 0x508daf42, 0x1884fc2b, 0x7214f24c, 0x7498be0a, 0x1a60e640,
+        0x3303f928, 0x29b46376, 0x5c96bb68, 0x65d097a5, 0x1d358e9f, 0x4a9a9017, 0x4724cf76,
+        0x347af70f, 0x1e77e59a,
+    ],
+    [
+        0x5c23bb9c, 0x5da64dbf, 0x7a2be1dd, 0x1d33a4d8, 0x484a3f8e, 0x007a706a, 0x57bd9767,
+        0x7149341f, 0x2667ecb6, 0x2db9150f, 0x32004141, 0x45c0728f, 0x62934143, 0x32c19c19,
+        0x29bd378d, 0x5b3d502e,
+    ],
+    [
+        0x62cb8455, 0x2b147f51, 0x621d052b, 0x11ba7123, 0x59bb54be, 0x61452bf6, 0x30babf3a,
+        0x215b97f7, 0x07700f00, 0x0dda07b1, 0x2ad97715, 0x2c78402d, 0x6096089e, 0x2465e76f,
+        0x5b490daa, 0x27d6349d,
+    ],
+    [
+        0x4e9d8c89, 0x7b36a67d, 0x41a27774, 0x71452a2a, 0x4d2f9d8d, 0x5c199518, 0x4c017e2c,
+        0x344f57b3, 0x70afd1ad, 0x5141ba4f, 0x2fe06654, 0x745d98a8, 0x0311922c, 0x335e3407,
+        0x5bfe6359, 0x74fb36b4,
+    ],
+    [
+        0x41a1e898, 0x5f17d56c, 0x30d1c4a2, 0x3380e1bb, 0x413b7490, 0x51ad85e6, 0x1a9760a3,
+        0x3533477a, 0x42d9b6cb, 0x284a99fc, 0x04eb39f8, 0x2f2b33ae, 0x5b21e6b2, 0x4ab941a2,
+        0x16ffcaa1, 0x3f02cbe3,
+    ],
+];
+
+pub const POSEIDON2_M31_INTERNAL_CONSTANTS: [u32; 14] = [
+    0x7f7ec4bf, 0x17bbef50, 0x66975762, 0x12771799, 0x1178907b, 0x13e05a67, 0x321ef96e, 0x685ffa6a,
+    0x14a370cd, 0x36a88dde, 0x4c87d08b, 0x789b76c2, 0x01062150, 0x27f6bea4,
+];
+
+#[cfg(test)]
+mod test {
+    use crate::poseidon2::test::{
+        test_poseidon2_constants_generic, test_poseidon2_external_layer_generic,
+        test_poseidon2_internal_layer_generic, test_poseidon2_known_answer_generic,
+        test_poseidon2_permute_generic,
+    };
+    use crate::{
+        poseidon2_external_layer, poseidon2_internal_layer, Poseidon2M31,
+        POSEIDON2_M31_EXTERNAL_CONSTANTS, POSEIDON2_M31_INTERNAL_CONSTANTS,
+    };
+    use p3_mersenne_31::DiffusionMatrixMersenne31;
+    use p3_mersenne_31::Mersenne31 as P3Field;
+
+    #[test]
+    fn test_poseidon2_internal_layer() {
+        eprintln!(
+            "m31 poseidon2 internal layer: {}",
+            poseidon2_internal_layer::<Poseidon2M31>().len()
+        );
+        test_poseidon2_internal_layer_generic::<Poseidon2M31, P3Field, _>(
+            DiffusionMatrixMersenne31,
+        );
+    }
+
+    #[test]
+    fn test_poseidon2_external_layer() {
+        eprintln!(
+            "m31 poseidon2 external layer: {}",
+            poseidon2_external_layer::<Poseidon2M31>().len()
+        );
+        test_poseidon2_external_layer_generic::<Poseidon2M31, P3Field>();
+    }
+
+    #[test]
+    fn test_poseidon2_permute() {
+        test_poseidon2_permute_generic::<Poseidon2M31, P3Field, _, 5>(DiffusionMatrixMersenne31);
+    }
+
+    #[test]
+    fn test_poseidon2_constants() {
+        test_poseidon2_constants_generic::<Poseidon2M31>(
+            &POSEIDON2_M31_EXTERNAL_CONSTANTS,
+            &POSEIDON2_M31_INTERNAL_CONSTANTS,
+        );
+    }
+
+    #[test]
+    fn test_poseidon2_known_answer() {
+        // the permutation of 0, 1, ..., 15
+        let expected = [
+            738214495, 262877645, 656307823, 1987365696, 477898995, 2038542078, 1620798478,
+            98212662, 1648474356, 1783955592, 988854123, 433290877, 1940156418, 132651986,
+            1942892029, 1144757232,
+        ];
+        test_poseidon2_known_answer_generic::<Poseidon2M31, P3Field, _, 5>(
+            DiffusionMatrixMersenne31,
+            &POSEIDON2_M31_EXTERNAL_CONSTANTS,
+            &POSEIDON2_M31_INTERNAL_CONSTANTS,
+            expected,
+        );
+    }
+}
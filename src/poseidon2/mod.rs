@@ -0,0 +1,411 @@
+use crate::{u31_add, u31_add_v31, u31_double, u31_mul_by_constant, u31_to_v31, U31Config};
+use bitvm::treepp::*;
+
+mod babybear;
+pub use babybear::*;
+
+mod m31;
+pub use m31::*;
+
+pub const POSEIDON2_WIDTH: usize = 16;
+
+pub trait Poseidon2Config {
+    type BaseFieldConfig: U31Config;
+    const ROUNDS_F: usize;
+    const ROUNDS_P: usize;
+
+    // the internal matrix is 1 + Diag(INTERNAL_DIAG)
+    const INTERNAL_DIAG: [u32; POSEIDON2_WIDTH];
+
+    fn sbox_impl() -> Script;
+}
+
+pub fn poseidon2_sbox<C: Poseidon2Config>() -> Script {
+    C::sbox_impl()
+}
+
+// Input: x3 x2 x1 x0
+// Output: y3 y2 y1 y0 where y = circ(2, 3, 1, 1) * x
+fn poseidon2_mat4<M: U31Config>() -> Script {
+    script! {
+        // t01
+        OP_2DUP { u31_add::<M>() }
+        // t23
+        4 OP_PICK 4 OP_PICK { u31_add::<M>() }
+        // t0123
+        OP_2DUP { u31_add::<M>() }
+        // t01123
+        OP_DUP 5 OP_PICK { u31_add::<M>() }
+        // t01233
+        OP_SWAP 7 OP_PICK { u31_add::<M>() }
+
+        // y0 = t01123 + t01
+        OP_OVER 4 OP_ROLL { u31_add::<M>() } OP_TOALTSTACK
+        // y1 = t01123 + 2 * x2
+        OP_SWAP 5 OP_ROLL { u31_double::<M>() } { u31_add::<M>() } OP_TOALTSTACK
+        // y2 = t01233 + t23
+        OP_DUP OP_ROT { u31_add::<M>() } OP_TOALTSTACK
+        // y3 = t01233 + 2 * x0
+        OP_SWAP { u31_double::<M>() } { u31_add::<M>() }
+
+        OP_NIP OP_NIP
+        OP_FROMALTSTACK OP_FROMALTSTACK OP_FROMALTSTACK
+    }
+}
+
+// Input: state[15], ..., state[1], state[0]
+// Output: circ(2 * M4, M4, M4, M4) * state
+pub fn poseidon2_external_layer<C: Poseidon2Config>() -> Script {
+    script! {
+        // apply M4 to each chunk, which ends with chunk 0 on the top
+        for _ in 0..4 {
+            { poseidon2_mat4::<C::BaseFieldConfig>() }
+            for _ in 0..4 {
+                15 OP_ROLL
+            }
+        }
+
+        // sums[k] = state[k] + state[k + 4] + state[k + 8] + state[k + 12], kept in the v31 form
+        for k in 0..4 {
+            { k } OP_PICK
+            { k + 5 } OP_PICK { u31_add::<C::BaseFieldConfig>() }
+            { k + 9 } OP_PICK { u31_add::<C::BaseFieldConfig>() }
+            { k + 13 } OP_PICK { u31_add::<C::BaseFieldConfig>() }
+            { u31_to_v31::<C::BaseFieldConfig>() }
+            OP_TOALTSTACK
+        }
+        OP_FROMALTSTACK OP_FROMALTSTACK OP_FROMALTSTACK OP_FROMALTSTACK
+
+        // state[i] += sums[i % 4]
+        for j in 0..POSEIDON2_WIDTH {
+            19 OP_ROLL
+            { j + 1 + (POSEIDON2_WIDTH - 1 - j) % 4 } OP_PICK
+            { u31_add_v31::<C::BaseFieldConfig>() }
+        }
+        for _ in 0..4 {
+            16 OP_ROLL OP_DROP
+        }
+    }
+}
+
+// Input: state[15], ..., state[1], state[0]
+// Output: (1 + Diag(INTERNAL_DIAG)) * state
+pub fn poseidon2_internal_layer<C: Poseidon2Config>() -> Script {
+    script! {
+        OP_DUP
+        for i in 1..POSEIDON2_WIDTH {
+            { i + 1 } OP_PICK
+            { u31_add::<C::BaseFieldConfig>() }
+        }
+        { u31_to_v31::<C::BaseFieldConfig>() }
+
+        for diag in C::INTERNAL_DIAG.iter() {
+            OP_SWAP
+            { u31_mul_by_constant::<C::BaseFieldConfig>(*diag) }
+            OP_OVER
+            { u31_add_v31::<C::BaseFieldConfig>() }
+            OP_TOALTSTACK
+        }
+        OP_DROP
+        for _ in 0..POSEIDON2_WIDTH {
+            OP_FROMALTSTACK
+        }
+    }
+}
+
+fn poseidon2_add_constant<M: U31Config>(constant: u32) -> Script {
+    script! {
+        { constant as i64 - M::MOD as i64 }
+        { u31_add_v31::<M>() }
+    }
+}
+
+// Input: state[15], ..., state[1], state[0]
+// Output: the Poseidon2 permutation of the state
+//
+// The rounds follow Plonky3: an initial external layer, ROUNDS_F / 2 full rounds,
+// ROUNDS_P partial rounds on state[0], and the remaining ROUNDS_F / 2 full rounds.
+pub fn poseidon2_permute<C: Poseidon2Config>(
+    external_constants: &[[u32; POSEIDON2_WIDTH]],
+    internal_constants: &[u32],
+) -> Script {
+    assert_eq!(external_constants.len(), C::ROUNDS_F);
+    assert_eq!(internal_constants.len(), C::ROUNDS_P);
+
+    let full_round = |constants: &[u32; POSEIDON2_WIDTH]| {
+        script! {
+            for constant in constants.iter().rev() {
+                15 OP_ROLL
+                { poseidon2_add_constant::<C::BaseFieldConfig>(*constant) }
+                { poseidon2_sbox::<C>() }
+            }
+            { poseidon2_external_layer::<C>() }
+        }
+    };
+
+    script! {
+        { poseidon2_external_layer::<C>() }
+        for constants in external_constants[..C::ROUNDS_F / 2].iter() {
+            { full_round(constants) }
+        }
+        for constant in internal_constants.iter() {
+            { poseidon2_add_constant::<C::BaseFieldConfig>(*constant) }
+            { poseidon2_sbox::<C>() }
+            { poseidon2_internal_layer::<C>() }
+        }
+        for constants in external_constants[C::ROUNDS_F / 2..].iter() {
+            { full_round(constants) }
+        }
+    }
+}
+
+// Checks the scripts against the Poseidon2 of Plonky3, with its own diffusion matrix for the
+// field. The differential tests use random round constants; the standard ones shipped for each
+// field are regenerated from the Grain LFSR and checked against a known answer.
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::{
+        poseidon2_external_layer, poseidon2_internal_layer, poseidon2_permute, Poseidon2Config,
+        U31Config, POSEIDON2_WIDTH,
+    };
+    use bitvm::treepp::*;
+    use p3_field::PrimeField32;
+    use p3_poseidon2::{DiffusionPermutation, Poseidon2, Poseidon2ExternalMatrixGeneral};
+    use p3_symmetric::Permutation;
+    use rand::distributions::{Distribution, Standard};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use std::collections::VecDeque;
+
+    fn check_layer<F: PrimeField32>(
+        layer: Script,
+        input: &[F; POSEIDON2_WIDTH],
+        output: &[F; POSEIDON2_WIDTH],
+    ) {
+        let script = script! {
+            for x in input.iter().rev() {
+                { x.as_canonical_u32() }
+            }
+            { layer }
+            for x in output.iter() {
+                { x.as_canonical_u32() }
+                OP_EQUALVERIFY
+            }
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
+    pub(crate) fn test_poseidon2_internal_layer_generic<C, F, D>(diffusion: D)
+    where
+        C: Poseidon2Config,
+        F: PrimeField32,
+        D: DiffusionPermutation<F, POSEIDON2_WIDTH>,
+        Standard: Distribution<F>,
+    {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+
+        // the diagonal of Plonky3, read off its matrix applied to the unit vectors
+        for (i, diag) in C::INTERNAL_DIAG.iter().enumerate() {
+            let mut unit = [F::zero(); POSEIDON2_WIDTH];
+            unit[i] = F::one();
+            let column = diffusion.permute(unit);
+            assert_eq!((column[i] - F::one()).as_canonical_u32(), *diag);
+        }
+
+        for _ in 0..4 {
+            let input: [F; POSEIDON2_WIDTH] = prng.gen();
+            let output = diffusion.permute(input);
+            check_layer(poseidon2_internal_layer::<C>(), &input, &output);
+        }
+    }
+
+    pub(crate) fn test_poseidon2_external_layer_generic<C, F>()
+    where
+        C: Poseidon2Config,
+        F: PrimeField32,
+        Poseidon2ExternalMatrixGeneral: Permutation<[F; POSEIDON2_WIDTH]>,
+        Standard: Distribution<F>,
+    {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+
+        for _ in 0..4 {
+            let input: [F; POSEIDON2_WIDTH] = prng.gen();
+            let output = Poseidon2ExternalMatrixGeneral.permute(input);
+            check_layer(poseidon2_external_layer::<C>(), &input, &output);
+        }
+    }
+
+    pub(crate) fn test_poseidon2_permute_generic<C, F, D, const SBOX_DEGREE: u64>(diffusion: D)
+    where
+        C: Poseidon2Config,
+        F: PrimeField32,
+        D: DiffusionPermutation<F, POSEIDON2_WIDTH>,
+        Poseidon2<F, Poseidon2ExternalMatrixGeneral, D, POSEIDON2_WIDTH, SBOX_DEGREE>:
+            Permutation<[F; POSEIDON2_WIDTH]>,
+        Standard: Distribution<F>,
+    {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        let modulus = C::BaseFieldConfig::MOD;
+
+        let external_constants: Vec<[u32; POSEIDON2_WIDTH]> = (0..C::ROUNDS_F)
+            .map(|_| core::array::from_fn(|_| prng.gen::<u32>() % modulus))
+            .collect();
+        let internal_constants: Vec<u32> = (0..C::ROUNDS_P)
+            .map(|_| prng.gen::<u32>() % modulus)
+            .collect();
+
+        let poseidon2 =
+            Poseidon2::<F, Poseidon2ExternalMatrixGeneral, D, POSEIDON2_WIDTH, SBOX_DEGREE>::new(
+                C::ROUNDS_F,
+                external_constants
+                    .iter()
+                    .map(|c| c.map(F::from_canonical_u32))
+                    .collect(),
+                Poseidon2ExternalMatrixGeneral,
+                C::ROUNDS_P,
+                internal_constants
+                    .iter()
+                    .map(|c| F::from_canonical_u32(*c))
+                    .collect(),
+                diffusion,
+            );
+
+        let permute_script = poseidon2_permute::<C>(&external_constants, &internal_constants);
+        eprintln!("poseidon2 permutation: {}", permute_script.len());
+
+        for _ in 0..4 {
+            let input: [F; POSEIDON2_WIDTH] = prng.gen();
+            let output = poseidon2.permute(input);
+            check_layer(permute_script.clone(), &input, &output);
+        }
+    }
+
+    // The Grain LFSR with which the Poseidon2 reference implementation draws its round constants
+    struct Grain(VecDeque<bool>);
+
+    impl Grain {
+        fn new(rounds_f: usize, rounds_p: usize) -> Self {
+            let mut bits = VecDeque::new();
+            // a prime field, the x^alpha S-box, 31-bit elements, the width and the rounds
+            for (value, len) in [
+                (1, 2),
+                (0, 4),
+                (31, 12),
+                (POSEIDON2_WIDTH, 12),
+                (rounds_f, 10),
+                (rounds_p, 10),
+            ] {
+                for i in (0..len).rev() {
+                    bits.push_back((value >> i) & 1 == 1);
+                }
+            }
+            bits.extend([true; 30]);
+
+            let mut grain = Self(bits);
+            for _ in 0..160 {
+                grain.step();
+            }
+            grain
+        }
+
+        fn step(&mut self) -> bool {
+            let bits = &self.0;
+            let bit = bits[62] ^ bits[51] ^ bits[38] ^ bits[23] ^ bits[13] ^ bits[0];
+            self.0.pop_front();
+            self.0.push_back(bit);
+            bit
+        }
+
+        // the self-shrinking generator keeps the second bit of each pair whose first bit is 1
+        fn next_bit(&mut self) -> bool {
+            loop {
+                let keep = self.step();
+                let bit = self.step();
+                if keep {
+                    return bit;
+                }
+            }
+        }
+
+        fn next_element(&mut self, modulus: u32) -> u32 {
+            loop {
+                let x = (0..31).fold(0u32, |acc, _| (acc << 1) | self.next_bit() as u32);
+                if x < modulus {
+                    return x;
+                }
+            }
+        }
+    }
+
+    // Output: the external and internal round constants of the reference implementation
+    fn grain_round_constants<C: Poseidon2Config>() -> (Vec<[u32; POSEIDON2_WIDTH]>, Vec<u32>) {
+        let mut grain = Grain::new(C::ROUNDS_F, C::ROUNDS_P);
+        let rows: Vec<[u32; POSEIDON2_WIDTH]> = (0..C::ROUNDS_F + C::ROUNDS_P)
+            .map(|_| core::array::from_fn(|_| grain.next_element(C::BaseFieldConfig::MOD)))
+            .collect();
+
+        let half = C::ROUNDS_F / 2;
+        let external_constants = rows[..half]
+            .iter()
+            .chain(rows[half + C::ROUNDS_P..].iter())
+            .copied()
+            .collect();
+        let internal_constants = rows[half..half + C::ROUNDS_P]
+            .iter()
+            .map(|row| row[0])
+            .collect();
+        (external_constants, internal_constants)
+    }
+
+    pub(crate) fn test_poseidon2_constants_generic<C: Poseidon2Config>(
+        external_constants: &[[u32; POSEIDON2_WIDTH]],
+        internal_constants: &[u32],
+    ) {
+        let (expected_external, expected_internal) = grain_round_constants::<C>();
+        assert_eq!(external_constants, expected_external.as_slice());
+        assert_eq!(internal_constants, expected_internal.as_slice());
+    }
+
+    // Checks the permutation of 0, 1, ..., 15 with the given constants against both the expected
+    // output and Plonky3
+    pub(crate) fn test_poseidon2_known_answer_generic<C, F, D, const SBOX_DEGREE: u64>(
+        diffusion: D,
+        external_constants: &[[u32; POSEIDON2_WIDTH]],
+        internal_constants: &[u32],
+        expected: [u32; POSEIDON2_WIDTH],
+    ) where
+        C: Poseidon2Config,
+        F: PrimeField32,
+        D: DiffusionPermutation<F, POSEIDON2_WIDTH>,
+        Poseidon2<F, Poseidon2ExternalMatrixGeneral, D, POSEIDON2_WIDTH, SBOX_DEGREE>:
+            Permutation<[F; POSEIDON2_WIDTH]>,
+    {
+        let poseidon2 =
+            Poseidon2::<F, Poseidon2ExternalMatrixGeneral, D, POSEIDON2_WIDTH, SBOX_DEGREE>::new(
+                C::ROUNDS_F,
+                external_constants
+                    .iter()
+                    .map(|c| c.map(F::from_canonical_u32))
+                    .collect(),
+                Poseidon2ExternalMatrixGeneral,
+                C::ROUNDS_P,
+                internal_constants
+                    .iter()
+                    .map(|c| F::from_canonical_u32(*c))
+                    .collect(),
+                diffusion,
+            );
+
+        let input: [F; POSEIDON2_WIDTH] = core::array::from_fn(|i| F::from_canonical_u32(i as u32));
+        let output = poseidon2.permute(input);
+        assert_eq!(output.map(|x| x.as_canonical_u32()), expected);
+
+        check_layer(
+            poseidon2_permute::<C>(external_constants, internal_constants),
+            &input,
+            &output,
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::Opcode;
+use bitcoin::script::Instruction;
+use bitvm::treepp::*;
+
+// Bitcoin fails a script once the stack and the altstack hold more than 1000 elements together.
+pub const MAX_STACK_ITEMS: usize = 1000;
+
+// How a script uses the stack and the altstack, relative to where it starts
+pub struct StackUsage {
+    // the peak growth of the stack and the altstack together
+    pub max_growth: usize,
+    // the peak size of the altstack, counting from its size at the start
+    pub max_altstack: usize,
+    pub stack_delta: isize,
+    pub altstack_delta: isize,
+    // how many elements below its start the script reaches, i.e., how many inputs it takes
+    pub max_depth: usize,
+}
+
+impl StackUsage {
+    // whether the script stays within the limit when given `inputs` elements (on top of
+    // anything already on the stack and the altstack, which is not counted)
+    pub fn fits(&self, inputs: usize) -> bool {
+        inputs + self.max_growth <= MAX_STACK_ITEMS
+    }
+}
+
+// Follows the script statically. The stack effect of every opcode used in this crate is fixed
+// (OP_PICK and OP_ROLL take one element whatever their argument), so this needs no inputs. For
+// an OP_IF, the larger of the two branches counts, and the branches are expected to leave the
+// stack the same way, as all scripts in this crate do.
+//
+// An OP_PICK or OP_ROLL reaches as deep as its argument when the argument is pushed right before
+// it, as for all the deep ones in this crate. Otherwise it is taken to pick among the elements
+// that the script pushed itself, as the table lookups of u31_mul do.
+//
+// Returns None for a script that does not parse, has unbalanced OP_IF/OP_ELSE/OP_ENDIF, or
+// uses an opcode whose stack effect is not fixed or not known here (e.g. OP_IFDUP or
+// OP_CHECKMULTISIG), since no bound can be given for it.
+pub fn stack_usage(script: &Script) -> Option<StackUsage> {
+    // the sizes at each open OP_IF, and at the end of its first branch once past OP_ELSE
+    let mut branches: Vec<((isize, isize), Option<(isize, isize)>)> = vec![];
+    let (mut stack, mut altstack) = (0isize, 0isize);
+    let (mut max_growth, mut max_altstack) = (0isize, 0isize);
+    // the lowest the stack gets, and the number pushed by the previous instruction if any
+    let mut min_stack = 0isize;
+    let mut last_number: Option<isize> = None;
+
+    for instruction in script.instructions() {
+        let instruction = instruction.ok()?;
+        let number = push_number(&instruction);
+        match instruction {
+            Instruction::PushBytes(_) => stack += 1,
+            Instruction::Op(op) => match op {
+                OP_IF | OP_NOTIF => {
+                    min_stack = min_stack.min(stack - 1);
+                    stack -= 1;
+                    branches.push(((stack, altstack), None));
+                }
+                OP_ELSE => {
+                    let branch = branches.last_mut()?;
+                    if branch.1.is_some() {
+                        return None;
+                    }
+                    branch.1 = Some((stack, altstack));
+                    (stack, altstack) = branch.0;
+                }
+                OP_ENDIF => {
+                    let (start, first) = branches.pop()?;
+                    let first = first.unwrap_or(start);
+                    stack = stack.max(first.0);
+                    altstack = altstack.max(first.1);
+                }
+                OP_TOALTSTACK => {
+                    min_stack = min_stack.min(stack - 1);
+                    stack -= 1;
+                    altstack += 1;
+                }
+                OP_FROMALTSTACK => {
+                    stack += 1;
+                    altstack -= 1;
+                }
+                _ => {
+                    let (taken, left) = opcode_stack_effect(op)?;
+                    if op == OP_PICK || op == OP_ROLL {
+                        // the argument and the elements down to the one picked
+                        if let Some(n) = last_number.filter(|n| *n >= 0) {
+                            min_stack = min_stack.min(stack - n - 2);
+                        }
+                    }
+                    min_stack = min_stack.min(stack - taken);
+                    stack += left - taken;
+                }
+            },
+        }
+        last_number = number;
+        max_growth = max_growth.max(stack + altstack);
+        max_altstack = max_altstack.max(altstack);
+    }
+
+    if !branches.is_empty() {
+        return None;
+    }
+
+    Some(StackUsage {
+        max_growth: max_growth as usize,
+        max_altstack: max_altstack as usize,
+        stack_delta: stack,
+        altstack_delta: altstack,
+        max_depth: (-min_stack) as usize,
+    })
+}
+
+// The number pushed by a push of at most 4 bytes or by an OP_PUSHNUM
+fn push_number(instruction: &Instruction) -> Option<isize> {
+    match instruction {
+        Instruction::PushBytes(bytes) => {
+            let bytes = bytes.as_bytes();
+            if bytes.len() > 4 {
+                return None;
+            }
+            let mut value = 0isize;
+            for (i, byte) in bytes.iter().enumerate() {
+                value |= (*byte as isize) << (8 * i);
+            }
+            // the top bit of the last byte is the sign
+            match bytes.last() {
+                Some(last) if last & 0x80 != 0 => {
+                    Some(-(value & !(0x80 << (8 * (bytes.len() - 1)))))
+                }
+                _ => Some(value),
+            }
+        }
+        Instruction::Op(op) if *op == OP_PUSHNUM_NEG1 => Some(-1),
+        Instruction::Op(op) => {
+            let op = op.to_u8();
+            if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op) {
+                Some((op - OP_PUSHNUM_1.to_u8() + 1) as isize)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// The number of elements that an opcode takes from the top of the stack, and the number it
+// leaves in their place
+fn opcode_stack_effect(op: Opcode) -> Option<(isize, isize)> {
+    let effect = match op {
+        OP_PUSHNUM_NEG1 | OP_DEPTH => (0, 1),
+        OP_DUP | OP_SIZE => (1, 2),
+        OP_OVER | OP_TUCK => (2, 3),
+        OP_2DUP => (2, 4),
+        OP_2OVER => (4, 6),
+        OP_3DUP => (3, 6),
+        OP_PICK => (1, 1),
+        OP_SWAP => (2, 2),
+        OP_ROT => (3, 3),
+        OP_2SWAP => (4, 4),
+        OP_2ROT => (6, 6),
+        OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => (1, 1),
+        OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => (1, 1),
+        OP_NOP | OP_CODESEPARATOR => (0, 0),
+        OP_CLTV | OP_CSV => (1, 1),
+        OP_ROLL | OP_DROP | OP_VERIFY => (1, 0),
+        OP_NIP => (2, 1),
+        OP_ADD | OP_SUB | OP_BOOLAND | OP_BOOLOR | OP_NUMEQUAL | OP_NUMNOTEQUAL => (2, 1),
+        OP_LESSTHAN | OP_GREATERTHAN | OP_LESSTHANOREQUAL | OP_GREATERTHANOREQUAL => (2, 1),
+        OP_MIN | OP_MAX | OP_EQUAL | OP_CAT | OP_CHECKSIG => (2, 1),
+        OP_2DROP | OP_EQUALVERIFY | OP_NUMEQUALVERIFY | OP_CHECKSIGVERIFY => (2, 0),
+        OP_WITHIN | OP_CHECKSIGADD => (3, 1),
+        _ if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) => (0, 1),
+        _ => return None,
+    };
+    Some(effect)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        commit_public_key, deep_quotient, karatsuba_big, merkle_verify_path, stack_usage, u31_mul,
+        u31_mul_many, u31ext_batch_inv, u31ext_many_mul_u31, u31ext_mul, u31ext_mul_u31, BabyBear,
+        BabyBear4, DomainConfig, FieldScript, U31ExtConfig, M31, QM31,
+    };
+    use bitvm::treepp::*;
+
+    const SECRET_KEY: &str = "b138982ce17ac813d505b5b40b665d404e9528e7";
+
+    #[test]
+    fn test_stack_usage_primitives() {
+        // u31_mul keeps the 31 bits of one operand on the altstack
+        let usage = stack_usage(&u31_mul::<BabyBear>()).unwrap();
+        assert_eq!(usage.max_altstack, 31);
+        assert_eq!((usage.stack_delta, usage.altstack_delta), (-1, 0));
+
+        // u31ext_mul_u31 keeps a copy of the 31 bits for each of the 4 limbs
+        let usage = stack_usage(&u31ext_mul_u31::<QM31>()).unwrap();
+        assert_eq!(usage.max_altstack, 124);
+        assert_eq!((usage.stack_delta, usage.altstack_delta), (-1, 0));
+
+        let usage = stack_usage(&karatsuba_big::<M31>()).unwrap();
+        assert_eq!((usage.stack_delta, usage.altstack_delta), (1, 0));
+    }
+
+    fn check_declared_stack_effect(fs: &FieldScript) {
+        let usage = stack_usage(&fs.script).unwrap();
+        assert_eq!(usage.max_depth, fs.inputs);
+        assert_eq!(usage.stack_delta, fs.outputs as isize - fs.inputs as isize);
+        assert_eq!(usage.altstack_delta, fs.altstack_delta);
+        assert!(usage.fits(fs.inputs));
+    }
+
+    // The statically computed stack effect agrees with the one declared by FieldScript, inputs
+    // included.
+    fn test_stack_usage_field_scripts_generic<C: DomainConfig>(coset_shift: C::CosetShift) {
+        let public_key = commit_public_key(SECRET_KEY);
+
+        for fs in [
+            FieldScript::u31_add::<C::BaseFieldConfig>(),
+            FieldScript::u31_sub::<C::BaseFieldConfig>(),
+            FieldScript::u31_double::<C::BaseFieldConfig>(),
+            FieldScript::u31_neg::<C::BaseFieldConfig>(),
+            FieldScript::u31_to_v31::<C::BaseFieldConfig>(),
+            FieldScript::v31_to_u31::<C::BaseFieldConfig>(),
+            FieldScript::u31_adjust::<C::BaseFieldConfig>(),
+            FieldScript::v31_adjust::<C::BaseFieldConfig>(),
+            FieldScript::u31_add_v31::<C::BaseFieldConfig>(),
+            FieldScript::v31_add_u31::<C::BaseFieldConfig>(),
+            FieldScript::v31_add::<C::BaseFieldConfig>(),
+            FieldScript::v31_sub::<C::BaseFieldConfig>(),
+            FieldScript::v31_double::<C::BaseFieldConfig>(),
+            FieldScript::v31_neg::<C::BaseFieldConfig>(),
+            FieldScript::u31_mul::<C::BaseFieldConfig>(),
+            FieldScript::u31_mul_hinted::<C::BaseFieldConfig>(),
+            FieldScript::u31_mul_many::<C::BaseFieldConfig>(5),
+            FieldScript::u31_batch_inv::<C::BaseFieldConfig>(5),
+            FieldScript::u31_batch_inv_hinted::<C::BaseFieldConfig>(5),
+            FieldScript::u31_to_bits_n(10),
+            FieldScript::u31_to_bits_lsb_first(10),
+            FieldScript::u31_from_bits(10),
+            FieldScript::u31_to_le_bytes::<C::BaseFieldConfig>(),
+            FieldScript::u31_vec_hash::<C::BaseFieldConfig>(5),
+            FieldScript::u31_commit_and_verify::<C::BaseFieldConfig>(&public_key),
+            FieldScript::u31_commit_and_check::<C::BaseFieldConfig>(&public_key),
+            FieldScript::karatsuba_small::<C::BaseFieldConfig>(),
+            FieldScript::karatsuba_big::<C::BaseFieldConfig>(),
+            FieldScript::u31ext_add::<C>(),
+            FieldScript::u31ext_sub::<C>(),
+            FieldScript::u31ext_mul::<C>(),
+            FieldScript::u31ext_mul_u31::<C>(),
+            FieldScript::u31ext_mul_u31_by_constant::<C>(7),
+            FieldScript::u31ext_many_mul_u31::<C>(3),
+            FieldScript::u31ext_batch_inv::<C>(3),
+            FieldScript::u31ext_batch_inv_hinted::<C>(3),
+            FieldScript::u31ext_hash::<C>(),
+            FieldScript::u31ext_commit_and_verify::<C>(&public_key),
+            FieldScript::u31ext_commit_and_check::<C>(&public_key),
+            FieldScript::merkle_verify_path::<C>(5),
+            FieldScript::merkle_hash_node(),
+            FieldScript::deep_quotient::<C>(3),
+            FieldScript::deep_quotient_checked::<C>(3),
+            FieldScript::vanishing_eval::<C>(10, coset_shift),
+            FieldScript::lagrange_first_row::<C>(10, coset_shift),
+            FieldScript::lagrange_last_row::<C>(10, coset_shift),
+            FieldScript::u31ext_copy::<C>(3),
+            FieldScript::u31ext_roll::<C>(3),
+            FieldScript::u31ext_toaltstack::<C>(),
+            FieldScript::u31ext_fromaltstack::<C>(),
+        ] {
+            check_declared_stack_effect(&fs);
+        }
+    }
+
+    #[test]
+    fn test_stack_usage_field_scripts() {
+        test_stack_usage_field_scripts_generic::<QM31>((1, 0));
+        test_stack_usage_field_scripts_generic::<BabyBear4>(31);
+
+        for fs in [
+            FieldScript::karatsuba_complex_small::<M31>(),
+            FieldScript::karatsuba_complex_big::<M31>(),
+            FieldScript::qm31_complex_conjugate(),
+            FieldScript::qm31_pair_vanishing(),
+        ] {
+            check_declared_stack_effect(&fs);
+        }
+    }
+
+    // An OP_PICK or OP_ROLL of a pushed argument reaches below it, one of a computed argument is
+    // taken to stay within what the script pushed.
+    #[test]
+    fn test_stack_usage_depth() {
+        assert_eq!(stack_usage(&script! { 5 OP_PICK }).unwrap().max_depth, 6);
+        assert_eq!(
+            stack_usage(&script! { 200 OP_ROLL }).unwrap().max_depth,
+            201
+        );
+        assert_eq!(
+            stack_usage(&script! { 2 OP_PICK OP_2DROP })
+                .unwrap()
+                .max_depth,
+            3
+        );
+        assert_eq!(
+            stack_usage(&script! { 1 2 OP_ADD OP_PICK })
+                .unwrap()
+                .max_depth,
+            0
+        );
+        assert_eq!(stack_usage(&script! { OP_ADD }).unwrap().max_depth, 2);
+    }
+
+    // Large compositions used by a verifier stay within the limit.
+    #[test]
+    fn test_stack_usage_compositions() {
+        let degree = QM31::DEGREE as usize;
+
+        // 8 limbs sharing one bit decomposition hold 8 copies of it on the altstack, more limbs
+        // only one at a time
+        let usage = stack_usage(&u31ext_many_mul_u31::<QM31>(2)).unwrap();
+        assert!(usage.max_altstack >= 8 * 31);
+        let usage = stack_usage(&u31ext_many_mul_u31::<QM31>(100)).unwrap();
+        eprintln!("qm31 many mul_by_m31 (100 elements): {}", usage.max_growth);
+        assert!(usage.max_altstack < 100 * degree + 2 * 31);
+        assert!(usage.fits(100 * degree + 1));
+
+        let n_columns = 100;
+        let usage = stack_usage(&deep_quotient::<QM31>(n_columns)).unwrap();
+        eprintln!("qm31 deep quotient (100 columns): {}", usage.max_growth);
+        assert!(usage.fits(3 * degree + 1 + n_columns * (degree + 1)));
+
+        let usage = stack_usage(&u31ext_batch_inv::<BabyBear4>(32)).unwrap();
+        assert!(usage.fits(33 * degree));
+
+        let usage = stack_usage(&u31_mul_many::<M31>(64)).unwrap();
+        assert!(usage.fits(65));
+
+        // a whole Merkle path with the leaf and the 20 siblings below
+        let usage = stack_usage(&merkle_verify_path::<QM31>(20)).unwrap();
+        assert!(usage.fits(1 + 2 * 20 + degree));
+
+        // the extension field multiplication on top of 800 unrelated elements
+        let usage = stack_usage(&u31ext_mul::<QM31>()).unwrap();
+        assert!(usage.fits(800 + 2 * degree));
+    }
+
+    // Scripts whose stack usage cannot be bounded are reported as such instead of panicking.
+    #[test]
+    fn test_stack_usage_unknown() {
+        // OP_CHECKMULTISIG takes a number of elements given on the stack
+        let script = script! { 1 OP_DUP OP_CHECKMULTISIG };
+        assert!(stack_usage(&script).is_none());
+
+        // OP_IFDUP only duplicates a nonzero element
+        assert!(stack_usage(&script! { 0 OP_IFDUP }).is_none());
+
+        // a push that runs past the end of the script
+        let script = Script::from(vec![0x05, 0x01, 0x02]);
+        assert!(stack_usage(&script).is_none());
+
+        // unbalanced branches
+        assert!(stack_usage(&script! { 1 OP_IF 2 }).is_none());
+        assert!(stack_usage(&script! { 2 OP_ENDIF }).is_none());
+        assert!(stack_usage(&script! { 1 OP_IF 2 OP_ELSE 3 OP_ELSE 4 OP_ENDIF }).is_none());
+
+        let usage = stack_usage(&script! { 1 OP_IF 2 3 OP_ELSE 4 OP_ENDIF }).unwrap();
+        assert_eq!(usage.max_growth, 2);
+    }
+}
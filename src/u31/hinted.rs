@@ -0,0 +1,365 @@
+use crate::u31::U31Config;
+use bitvm::treepp::*;
+
+// a * b = q * MOD + r is checked over the integers in base 2^15 columns, where
+//      a = a0 + a1 * 2^15                      (a0 < 2^15, a1 < 2^16)
+//      b = sum_{t = 0}^{10} d_t * 8^t          (3-bit digits, d_10 < 2)
+//      q = q0 + q1 * 2^15 + q2 * 2^30
+//
+// All partial products stay below 2^31 and, unlike u31_mul, need no modular reduction, so
+// doubling is OP_DUP OP_ADD. The carries c_k between the columns are also supplied by the prover.
+//
+// Only r and the digits of b are range-checked. The script checks a = a0 + a1 * 2^15 and
+// b = sum d_t * 8^t, and the column equations, weighted by 2^(15k), telescope over the carries
+// into a * b = q * MOD + r. Script arithmetic is exact and fails on any operand beyond 4 bytes,
+// so this holds over the integers whatever a0, a1, q0, q1, q2 and the carries are, and with
+// 0 <= r < MOD it leaves r = a * b mod MOD as the only remainder. Limbs or carries out of range
+// can only make the script fail. The digits are checked to be in [0, 8) because they select
+// the entries of the tables.
+const LIMB_BITS: usize = 15;
+const NUM_DIGITS: usize = 11;
+
+// the number of elements of the hint of u31_mul_hinted
+pub const U31_MUL_HINT_LEN: usize = 10 + NUM_DIGITS;
+
+// hint layout, from the bottom: r, q0, q1, q2, c3, c2, c1, c0, a1, a0, d_0, ..., d_10
+pub fn u31_mul_hint<M: U31Config>(a: u32, b: u32) -> Vec<i64> {
+    let product = a as u64 * b as u64;
+    let r = (product % M::MOD as u64) as i64;
+    let q = (product / M::MOD as u64) as i64;
+    u31_mul_hint_with::<M>(a, b, r, q, a as i64 >> LIMB_BITS)
+}
+
+// The hint for any r, q and a1 such that a * b = q * MOD + r, with a0 = a - a1 * 2^15
+fn u31_mul_hint_with<M: U31Config>(a: u32, b: u32, r: i64, q: i64, a1: i64) -> Vec<i64> {
+    let (a, b) = (a as i64, b as i64);
+
+    let mask = (1i64 << LIMB_BITS) - 1;
+    let q_limbs = [q & mask, (q >> LIMB_BITS) & mask, q >> (2 * LIMB_BITS)];
+    let a0 = a - (a1 << LIMB_BITS);
+    let (b0, b1, b2) = (b & mask, (b >> LIMB_BITS) & mask, b >> (2 * LIMB_BITS));
+
+    let mut columns = [
+        a0 * b0 - r,
+        a1 * b0 + a0 * b1,
+        a1 * b1 + a0 * b2,
+        a1 * b2,
+        0,
+    ];
+    for (e, s) in mod_naf::<M>() {
+        for (i, q_limb) in q_limbs.iter().enumerate() {
+            columns[i + e / LIMB_BITS] -= s * q_limb * (1 << (e % LIMB_BITS));
+        }
+    }
+
+    let mut carries = vec![];
+    let mut carry = 0i64;
+    for column in columns.iter().take(4) {
+        let v = column + carry;
+        assert_eq!(v & mask, 0);
+        carry = v >> LIMB_BITS;
+        carries.push(carry);
+    }
+    assert_eq!(columns[4] + carry, 0);
+
+    let mut hint = vec![r, q_limbs[0], q_limbs[1], q_limbs[2]];
+    hint.extend(carries.iter().rev());
+    hint.push(a1);
+    hint.push(a0);
+    for t in 0..NUM_DIGITS {
+        hint.push((b >> (3 * t)) & 7);
+    }
+    hint
+}
+
+// Input: hint (see u31_mul_hint), a, b
+// Output: a * b
+pub fn u31_mul_hinted<M: U31Config>() -> Script {
+    assert!(M::MOD > 1 << 30 && M::MOD < 1 << 31);
+    let naf = mod_naf::<M>();
+
+    script! {
+        // check a = a0 + a1 * 2^15
+        OP_SWAP
+        13 OP_ROLL
+        OP_TUCK OP_SUB
+        14 OP_ROLL
+        OP_TUCK
+        { shl(LIMB_BITS) }
+        OP_EQUALVERIFY
+
+        // tables of 0, a1, ..., 7 * a1 and 0, a0, ..., 7 * a0
+        { u31_mul_hinted_table() }
+        8 OP_ROLL
+        { u31_mul_hinted_table() }
+
+        // b, a0 * b, a1 * b, accumulated one digit at a time over the top digit, the upper 15
+        // bits and the lower 15 bits, moving the products of the first two to the altstack
+        0 0 0
+        { u31_mul_hinted_digit() }
+        OP_TOALTSTACK OP_TOALTSTACK
+        0 0
+        for _ in 0..5 {
+            { u31_mul_hinted_digit() }
+        }
+        OP_TOALTSTACK OP_TOALTSTACK
+        0 0
+        for _ in 0..5 {
+            { u31_mul_hinted_digit() }
+        }
+
+        // check the digits of b
+        OP_ROT
+        19 OP_ROLL
+        OP_EQUALVERIFY
+
+        OP_TOALTSTACK OP_TOALTSTACK
+        for _ in 0..8 {
+            OP_2DROP
+        }
+
+        for k in 0..5 {
+            { u31_mul_hinted_column(k, &naf) }
+        }
+        OP_2DROP OP_DROP
+
+        OP_DUP 0 { M::MOD } OP_WITHIN OP_VERIFY
+    }
+}
+
+// Input: r, q0, q1, q2, c3, ..., c_k, c_{k-1} (if k > 0)
+// Output: r, q0, q1, q2, c3, ..., c_k
+//
+// Checks that the products of column k, taken from the altstack, satisfy
+//      products + c_{k-1} - c_k * 2^15 - (q * MOD)_k = (r if k = 0 else 0)
+fn u31_mul_hinted_column(k: usize, naf: &[(usize, i64)]) -> Script {
+    // number of products of the limbs of a and b in each column
+    let num_products = [1, 2, 2, 1, 0];
+
+    let mut script_bytes = vec![];
+    if k < 4 {
+        if k > 0 {
+            script_bytes.extend_from_slice(script! { OP_SWAP }.as_bytes());
+        }
+        // subtract c_k * 2^14 twice so that no partial sum leaves the 4-byte range
+        script_bytes.extend_from_slice(
+            script! {
+                OP_DUP
+                { shl(LIMB_BITS - 1) }
+                OP_FROMALTSTACK OP_OVER OP_SUB
+            }
+            .as_bytes(),
+        );
+        if num_products[k] == 2 {
+            script_bytes.extend_from_slice(script! { OP_FROMALTSTACK OP_ADD }.as_bytes());
+        }
+        script_bytes.extend_from_slice(script! { OP_SWAP OP_SUB }.as_bytes());
+        if k > 0 {
+            script_bytes.extend_from_slice(script! { OP_ROT OP_ADD }.as_bytes());
+        }
+    }
+
+    // q0 sits right below the remaining carries and the partial sum
+    let q0_depth = if k < 4 { 4 - k + 3 } else { 3 };
+    for (e, s) in naf.iter() {
+        for i in 0..3 {
+            if i + e / LIMB_BITS == k {
+                script_bytes.extend_from_slice(
+                    script! {
+                        { q0_depth - i } OP_PICK
+                        { shl(e % LIMB_BITS) }
+                    }
+                    .as_bytes(),
+                );
+                if *s == 1 {
+                    script_bytes.extend_from_slice(script! { OP_SUB }.as_bytes());
+                } else {
+                    script_bytes.extend_from_slice(script! { OP_ADD }.as_bytes());
+                }
+            }
+        }
+    }
+
+    if k == 0 {
+        script_bytes.extend_from_slice(script! { 8 OP_PICK OP_EQUALVERIFY }.as_bytes());
+    } else {
+        script_bytes.extend_from_slice(script! { OP_NOT OP_VERIFY }.as_bytes());
+    }
+    Script::from(script_bytes)
+}
+
+// (e, s) for the nonzero digits s of MOD in NAF, i.e., MOD = sum s * 2^e
+fn mod_naf<M: U31Config>() -> Vec<(usize, i64)> {
+    ark_ff::biginteger::arithmetic::find_naf(&[M::MOD as u64])
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| **s != 0)
+        .map(|(e, s)| (e, *s as i64))
+        .collect()
+}
+
+fn shl(bits: usize) -> Script {
+    script! {
+        for _ in 0..bits {
+            OP_DUP OP_ADD
+        }
+    }
+}
+
+// Input: x
+// Output: 0, x, 2x, ..., 7x
+fn u31_mul_hinted_table() -> Script {
+    script! {
+        0 OP_SWAP
+        OP_DUP OP_DUP OP_ADD
+        for k in 3..8 {
+            OP_DUP { k - 1 } OP_PICK OP_ADD
+        }
+    }
+}
+
+// Input: d_{t+1..}, b, T1, T0, xb, x0, x1 (with d_t the topmost digit left)
+// Output: xb * 8 + d_t, x0 * 8 + a0 * d_t, x1 * 8 + a1 * d_t
+fn u31_mul_hinted_digit() -> Script {
+    script! {
+        20 OP_ROLL
+        OP_DUP 0 8 OP_WITHIN OP_VERIFY
+
+        OP_SWAP
+        { shl(3) }
+        OP_OVER 19 OP_SWAP OP_SUB OP_PICK OP_ADD
+        OP_TOALTSTACK
+
+        OP_SWAP
+        { shl(3) }
+        OP_OVER 10 OP_SWAP OP_SUB OP_PICK OP_ADD
+        OP_TOALTSTACK
+
+        OP_SWAP
+        { shl(3) }
+        OP_ADD
+        OP_FROMALTSTACK OP_FROMALTSTACK
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::u31_mul_hint_with;
+    use crate::{u31_mul_hint, u31_mul_hinted, BabyBear, U31Config, M31};
+    use bitvm::treepp::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn test_u31_mul_hinted_generic<M: U31Config>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+        let mut pairs = vec![
+            (0, 0),
+            (1, M::MOD - 1),
+            (M::MOD - 1, M::MOD - 1),
+            (1 << 30, 1 << 30),
+        ];
+        for _ in 0..100 {
+            pairs.push((prng.gen_range(0..M::MOD), prng.gen_range(0..M::MOD)));
+        }
+
+        for (a, b) in pairs {
+            let c = ((a as u64 * b as u64) % M::MOD as u64) as u32;
+            let hint = u31_mul_hint::<M>(a, b);
+
+            let script = script! {
+                for h in hint.iter() {
+                    { *h }
+                }
+                { a }
+                { b }
+                { u31_mul_hinted::<M>() }
+                { c }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            // a wrong remainder must be rejected
+            let mut wrong_hint = hint.clone();
+            wrong_hint[0] = ((c + 1) % M::MOD) as i64;
+
+            let script = script! {
+                for h in wrong_hint.iter() {
+                    { *h }
+                }
+                { a }
+                { b }
+                { u31_mul_hinted::<M>() }
+                OP_DROP
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(!exec_result.success);
+        }
+    }
+
+    fn run_with_hint<M: U31Config>(hint: &[i64], a: u32, b: u32, c: i64) -> bool {
+        let script = script! {
+            for h in hint.iter() {
+                { *h }
+            }
+            { a }
+            { b }
+            { u31_mul_hinted::<M>() }
+            { c }
+            OP_EQUAL
+        };
+        execute_script(script).success
+    }
+
+    // Hints forged to satisfy every equation the script checks
+    fn test_u31_mul_hinted_forged_generic<M: U31Config>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+        let p = M::MOD as i64;
+
+        for _ in 0..20 {
+            let a = prng.gen_range(1 << 16..M::MOD);
+            let b = prng.gen_range(8..M::MOD);
+            let product = a as i64 * b as i64;
+            let (q, r) = (product / p, product % p);
+
+            // a * b = (q + 1) * MOD + (r - MOD) only fails the range check on r
+            let hint = u31_mul_hint_with::<M>(a, b, r - p, q + 1, a as i64 >> 15);
+            assert!(!run_with_hint::<M>(&hint, a, b, r - p));
+
+            // a split of a with a0 >= 2^15 still gives a * b
+            let hint = u31_mul_hint_with::<M>(a, b, r, q, (a as i64 >> 15) - 1);
+            assert!(hint[9] >= 1 << 15);
+            assert!(run_with_hint::<M>(&hint, a, b, r));
+
+            // a carry that does not match its column
+            let mut hint = u31_mul_hint::<M>(a, b);
+            hint[7] += 1;
+            assert!(!run_with_hint::<M>(&hint, a, b, r));
+
+            // a digit of 8 or more, even with b = sum d_t * 8^t
+            let mut hint = u31_mul_hint::<M>(a, b);
+            let top = (10..21).rev().find(|i| hint[*i] > 0).unwrap();
+            if top > 10 {
+                hint[top] -= 1;
+                hint[top - 1] += 8;
+                assert!(!run_with_hint::<M>(&hint, a, b, r));
+            }
+        }
+    }
+
+    #[test]
+    fn test_u31_mul_hinted() {
+        eprintln!("m31 mul hinted: {}", u31_mul_hinted::<M31>().len());
+        eprintln!(
+            "babybear mul hinted: {}",
+            u31_mul_hinted::<BabyBear>().len()
+        );
+
+        test_u31_mul_hinted_generic::<M31>(0u64);
+        test_u31_mul_hinted_generic::<BabyBear>(1u64);
+        test_u31_mul_hinted_forged_generic::<M31>(2u64);
+        test_u31_mul_hinted_forged_generic::<BabyBear>(3u64);
+    }
+}
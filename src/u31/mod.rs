@@ -7,6 +7,9 @@ mod babybear;
 use crate::unroll;
 pub use babybear::*;
 
+mod hinted;
+pub use hinted::*;
+
 pub trait U31Config {
     const MOD: u32;
 }
@@ -37,7 +40,7 @@ pub fn v31_add_u31<M: U31Config>() -> Script {
     }
 }
 
-fn u31_adjust<M: U31Config>() -> Script {
+pub fn u31_adjust<M: U31Config>() -> Script {
     script! {
         OP_DUP
         0 OP_LESSTHAN
@@ -45,7 +48,7 @@ fn u31_adjust<M: U31Config>() -> Script {
     }
 }
 
-fn v31_adjust<M: U31Config>() -> Script {
+pub fn v31_adjust<M: U31Config>() -> Script {
     script! {
         OP_DUP
         0 OP_GREATERTHANOREQUAL
@@ -112,10 +115,22 @@ pub fn v31_neg<M: U31Config>() -> Script {
 }
 
 pub fn u31_to_bits() -> Script {
+    u31_to_bits_n(31)
+}
+
+// Input: a in [0, 2^n), which the script does NOT check
+// Output: bit[n - 1], ..., bit[1], bit[0]
+//
+// Any u31 is in range for n = 31. Out of range, bit[0] is left as the remainder of the
+// decomposition, a - (2^n - 2) > 1 for a >= 2^n and a itself for a < 0, so a caller that cannot
+// rule it out checks bit[0] with `OP_DUP 0 2 OP_WITHIN OP_VERIFY`.
+pub fn u31_to_bits_n(n: u32) -> Script {
+    assert!((1..=31).contains(&n));
+
     script! {
         {
-            unroll(30, |i| {
-                let a = 1 << (30 - i);
+            unroll(n - 1, |i| {
+                let a = 1 << (n - 1 - i);
                 let b = a - 1;
                 script! {
                     OP_DUP
@@ -127,43 +142,152 @@ pub fn u31_to_bits() -> Script {
     }
 }
 
-pub(crate) fn u31_mul_common<M: U31Config>() -> Script {
+// Input: a in [0, 2^n), which the script does NOT check
+// Output: bit[0], bit[1], ..., bit[n - 1]
+//
+// Out of range, bit[0], at the bottom, is not a bit, as in u31_to_bits_n.
+pub fn u31_to_bits_lsb_first(n: u32) -> Script {
+    assert!((1..=31).contains(&n));
+
+    script! {
+        {
+            unroll(n - 1, |i| {
+                let a = 1 << (n - 1 - i);
+                let b = a - 1;
+                script! {
+                    OP_DUP
+                    { b } OP_GREATERTHAN
+                    OP_DUP OP_TOALTSTACK
+                    OP_IF { a } OP_SUB OP_ENDIF
+                }
+        })}
+        { unroll(n - 1, |_| script! { OP_FROMALTSTACK }) }
+    }
+}
+
+// Input: bit[n - 1], ..., bit[1], bit[0]
+// Output: a = sum bit[i] * 2^i
+//
+// The bits go through OP_IF, so under the tapscript MINIMALIF rule anything other than 0 and 1
+// makes the script fail.
+pub fn u31_from_bits(n: u32) -> Script {
+    assert!((1..=31).contains(&n));
+
     script! {
         0
-        OP_SWAP
-        { u31_to_v31::<M>() }
-        OP_DUP
-        { v31_double::<M>() }
-        OP_2DUP
-        { v31_add::<M>() }
-        0
+        { unroll(n, |i| script! {
+            OP_SWAP
+            OP_IF { 1 << i } OP_ADD OP_ENDIF
+        }) }
+    }
+}
+
+// Input: a
+// Output: limb[k - 1], ..., limb[1], limb[0], the k = ceil(31 / limb_bits) limbs of a in base
+// 2^limb_bits
+pub(crate) fn u31_to_limbs(limb_bits: u32) -> Script {
+    assert!((1..=31).contains(&limb_bits));
+    let num_limbs = 31u32.div_ceil(limb_bits);
+    let top_limb_bits = 31 - limb_bits * (num_limbs - 1);
+
+    script! {
+        u31_to_bits
+        for _ in 0..31 {
+            OP_TOALTSTACK
+        }
+
         OP_FROMALTSTACK
-        OP_IF
-            3 OP_PICK
-            { u31_add_v31::<M>() }
-        OP_ENDIF
-        { u31_double::<M>() }
-        { u31_double::<M>() }
-        { unroll(14, |_| script! {
-            OP_FROMALTSTACK
+        for _ in 1..top_limb_bits {
+            OP_DUP OP_ADD OP_FROMALTSTACK OP_ADD
+        }
+        for _ in 1..num_limbs {
             OP_FROMALTSTACK
-            OP_SWAP OP_DUP OP_ADD OP_ADD
-            4 OP_SWAP OP_SUB OP_PICK
-            { u31_add_v31::<M>() }
-            { u31_double::<M>() }
-            { u31_double::<M>() }
-        })}
-        OP_FROMALTSTACK
-        OP_FROMALTSTACK
-        OP_SWAP OP_DUP OP_ADD OP_ADD
-        4 OP_SWAP OP_SUB OP_PICK
-        { u31_add_v31::<M>() }
+            for _ in 1..limb_bits {
+                OP_DUP OP_ADD OP_FROMALTSTACK OP_ADD
+            }
+        }
+    }
+}
+
+pub(crate) fn u31_mul_common<M: U31Config>() -> Script {
+    u31_mul_windowed_common::<M>(2)
+}
+
+// Input: a, with the 31 bits of b on the altstack (the most significant bit on top)
+// Output: a * b
+//
+// b is consumed w bits at a time, the most significant window first, with a lookup table of
+// 0, a, 2a, ..., (2^w - 1)a in the v31 form. If w does not divide 31, the first window is shorter.
+pub(crate) fn u31_mul_windowed_common<M: U31Config>(w: u32) -> Script {
+    script! {
+        { u31_mul_table::<M>(w) }
+        { u31_mul_table_lookup::<M>(w) }
         OP_TOALTSTACK
-        OP_2DROP OP_2DROP
+        { unroll((1 << w) / 2, |_| script! { OP_2DROP }) }
         OP_FROMALTSTACK
     }
 }
 
+// Input: a
+// Output: 0, a, 2a, ..., (2^w - 1)a, with all but 0 in the v31 form
+pub(crate) fn u31_mul_table<M: U31Config>(w: u32) -> Script {
+    assert!((1..=4).contains(&w));
+
+    script! {
+        0
+        OP_SWAP
+        { u31_to_v31::<M>() }
+        { unroll((1 << w) - 2, |i| match i + 2 {
+            2 => script! { OP_DUP { v31_double::<M>() } },
+            3 => script! { OP_2DUP { v31_add::<M>() } },
+            k => script! { OP_DUP { k - 1 } OP_PICK { v31_add::<M>() } },
+        }) }
+    }
+}
+
+// Input: the table of a (see u31_mul_table), with the 31 bits of b on the altstack
+// Output: the table of a, a * b
+pub(crate) fn u31_mul_table_lookup<M: U31Config>(w: u32) -> Script {
+    let table_size = 1u32 << w;
+    let mut windows = vec![];
+    if 31 % w != 0 {
+        windows.push(31 % w);
+    }
+    windows.extend(vec![w; (31 / w) as usize]);
+    let num_windows = windows.len();
+
+    script! {
+        0
+        { unroll(num_windows as u32, |j| {
+            let window = windows[j as usize];
+            let lookup = if window == 1 {
+                script! {
+                    OP_FROMALTSTACK
+                    OP_IF
+                        { table_size - 1 } OP_PICK
+                        { u31_add_v31::<M>() }
+                    OP_ENDIF
+                }
+            } else {
+                script! {
+                    OP_FROMALTSTACK
+                    { unroll(window - 1, |_| script! {
+                        OP_FROMALTSTACK
+                        OP_SWAP OP_DUP OP_ADD OP_ADD
+                    }) }
+                    { table_size } OP_SWAP OP_SUB OP_PICK
+                    { u31_add_v31::<M>() }
+                }
+            };
+            let doublings = if j as usize + 1 < num_windows { w } else { 0 };
+            script! {
+                { lookup }
+                { unroll(doublings, |_| u31_double::<M>()) }
+            }
+        }) }
+    }
+}
+
 pub fn u31_mul<M: U31Config>() -> Script {
     script! {
         u31_to_bits
@@ -174,6 +298,52 @@ pub fn u31_mul<M: U31Config>() -> Script {
     }
 }
 
+// Same as u31_mul with a window of w bits, 1 <= w <= 4. A wider window makes fewer additions and
+// lookups but a larger table; see the README for the cost of each window size.
+pub fn u31_mul_windowed<M: U31Config>(w: u32) -> Script {
+    script! {
+        u31_to_bits
+        { unroll(31, |_| script! {
+            OP_TOALTSTACK
+        }) }
+        { u31_mul_windowed_common::<M>(w) }
+    }
+}
+
+// Input: y_0, ..., y_{n-1}, x
+// Output: x * y_0, ..., x * y_{n-1}
+//
+// The lookup table of x is built once and shared by the n multiplications, so only the bit
+// decomposition and the accumulation are repeated for each y_i.
+pub fn u31_mul_many<M: U31Config>(n: usize) -> Script {
+    u31_mul_many_windowed::<M>(n, 2)
+}
+
+// Same as u31_mul_many with a window of w bits, 1 <= w <= 4. Since the table is shared,
+// wider windows become cheaper as n grows.
+pub fn u31_mul_many_windowed<M: U31Config>(n: usize, w: u32) -> Script {
+    let table_size = 1usize << w;
+
+    script! {
+        { u31_mul_table::<M>(w) }
+        for _ in 0..n {
+            { table_size } OP_ROLL
+            u31_to_bits
+            for _ in 0..31 {
+                OP_TOALTSTACK
+            }
+            { u31_mul_table_lookup::<M>(w) }
+            OP_TOALTSTACK
+        }
+        for _ in 0..table_size / 2 {
+            OP_2DROP
+        }
+        for _ in 0..n {
+            OP_FROMALTSTACK
+        }
+    }
+}
+
 pub fn u31_mul_by_constant<M: U31Config>(constant: u32) -> Script {
     let mut naf = ark_ff::biginteger::arithmetic::find_naf(&[constant as u64]);
 
@@ -256,6 +426,85 @@ pub fn u31_mul_by_constant<M: U31Config>(constant: u32) -> Script {
     Script::from(script_bytes)
 }
 
+// Input: h = (a_0 * ... * a_{n-1})^{-1} (supplied by the prover), a_0, ..., a_{n-1}
+// Output: a_0^{-1}, ..., a_{n-1}^{-1}
+//
+// Montgomery's trick: the prefix products take n - 1 multiplications, the only inverse, h, is
+// checked against the full product with one, and the inverses are recovered with 2(n - 1)
+// more, 3(n - 1) + 1 multiplications in all. u31_batch_inv_hinted hints every inverse instead,
+// which takes n multiplications but n times the witness.
+pub fn u31_batch_inv<M: U31Config>(n: usize) -> Script {
+    assert!(n >= 1);
+
+    script! {
+        // prefix products c_i = a_0 * ... * a_i
+        { n - 1 } OP_PICK
+        for _ in 1..n {
+            OP_DUP { n } OP_PICK
+            { u31_mul::<M>() }
+        }
+
+        // check c_{n-1} * h = 1
+        { 2 * n } OP_PICK
+        { u31_mul::<M>() }
+        1 OP_EQUALVERIFY
+        { 2 * n - 1 } OP_ROLL
+
+        for i in (1..n).rev() {
+            // a_i^{-1} = c_{i-1} * c_i^{-1}
+            OP_SWAP OP_OVER
+            { u31_mul::<M>() }
+            OP_TOALTSTACK
+
+            // c_{i-1}^{-1} = a_i * c_i^{-1}
+            { i } OP_ROLL
+            { u31_mul::<M>() }
+        }
+        OP_NIP
+        for _ in 1..n {
+            OP_FROMALTSTACK
+        }
+    }
+}
+
+// Input: a_0, ..., a_{n-1}, b_0, ..., b_{n-1} (b_i = a_i^{-1} supplied by the prover)
+// Output: b_0, ..., b_{n-1}
+//
+// Each claimed inverse is checked with a_i * b_i = 1.
+pub fn u31_batch_inv_hinted<M: U31Config>(n: usize) -> Script {
+    script! {
+        for k in 0..n {
+            OP_DUP { n - k + 1 } OP_ROLL
+            { u31_mul::<M>() }
+            1 OP_EQUALVERIFY
+            OP_TOALTSTACK
+        }
+        for _ in 0..n {
+            OP_FROMALTSTACK
+        }
+    }
+}
+
+// a^exp mod MOD
+pub fn u31_pow_native<M: U31Config>(a: u32, mut exp: u64) -> u32 {
+    let modulus = M::MOD as u64;
+    let mut base = a as u64 % modulus;
+    let mut res = 1u64;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            res = res * base % modulus;
+        }
+        base = base * base % modulus;
+        exp >>= 1;
+    }
+    res as u32
+}
+
+// a^{-1} mod MOD, for a nonzero a
+pub fn u31_inv_native<M: U31Config>(a: u32) -> u32 {
+    u31_pow_native::<M>(a, M::MOD as u64 - 2)
+}
+
 #[cfg(test)]
 mod test {
     use bitvm::treepp::*;
@@ -352,6 +601,95 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_u31_bits_round_trip() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!("u31 from bits: {}", u31_from_bits(31).len());
+
+        for n in [1, 2, 5, 20, 31] {
+            for _ in 0..20 {
+                let a = prng.gen::<u32>() % (1 << n);
+
+                let script = script! {
+                    { a }
+                    { u31_to_bits_n(n) }
+                    { u31_from_bits(n) }
+                    { a }
+                    OP_EQUAL
+                };
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+
+                // the bits of the least-significant-first decomposition come out in the
+                // reverse order, so undo it before recomposing
+                let script = script! {
+                    { a }
+                    { u31_to_bits_lsb_first(n) }
+                    for i in 1..n {
+                        { i } OP_ROLL
+                    }
+                    { u31_from_bits(n) }
+                    { a }
+                    OP_EQUAL
+                };
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+        }
+
+        let a = prng.gen::<u32>() % BabyBear::MOD;
+        let script = script! {
+            { a }
+            u31_to_bits
+            { u31_from_bits(31) }
+            { a }
+            OP_EQUAL
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    fn test_u31_to_bits_n_out_of_range() {
+        // bit[0] is the remainder, a - (2^n - 2) or a, outside of {0, 1}
+        for (n, a) in [(1, 2), (10, 1 << 10), (10, 5000), (20, 1 << 30), (10, -1)] {
+            let remainder = if a >= 0 { a - ((1 << n) - 2) } else { a };
+
+            let script = script! {
+                { a }
+                { u31_to_bits_n(n) }
+                OP_DUP { remainder } OP_EQUALVERIFY
+                0 2 OP_WITHIN OP_NOT OP_TOALTSTACK
+                for _ in 1..n {
+                    OP_DROP
+                }
+                OP_FROMALTSTACK
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_u31_to_bits_lsb_first() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+
+        for _ in 0..20 {
+            let a = prng.gen::<u32>() % M31::MOD;
+
+            let script = script! {
+                { a }
+                { u31_to_bits_lsb_first(31) }
+                for i in (0..31).rev() {
+                    { (a >> i) & 1 } OP_EQUALVERIFY
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
     #[test]
     fn test_u31_to_bits() {
         let mut prng = ChaCha20Rng::seed_from_u64(0u64);
@@ -452,6 +790,98 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_u31_mul_windowed() {
+        let mut prng = ChaCha20Rng::seed_from_u64(6u64);
+
+        for w in 1..=4 {
+            eprintln!(
+                "u31 mul (window {}): {}",
+                w,
+                u31_mul_windowed::<BabyBear>(w).len()
+            );
+
+            for _ in 0..20 {
+                let a = prng.gen_range(0..M31::MOD);
+                let b = prng.gen_range(0..M31::MOD);
+                let prod = ((a as u64 * b as u64) % (M31::MOD as u64)) as u32;
+
+                let script = script! {
+                    { a }
+                    { b }
+                    { u31_mul_windowed::<M31>(w) }
+                    { prod }
+                    OP_EQUAL
+                };
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+
+                let a = prng.gen_range(0..BabyBear::MOD);
+                let b = prng.gen_range(0..BabyBear::MOD);
+                let prod = ((a as u64 * b as u64) % (BabyBear::MOD as u64)) as u32;
+
+                let script = script! {
+                    { a }
+                    { b }
+                    { u31_mul_windowed::<BabyBear>(w) }
+                    { prod }
+                    OP_EQUAL
+                };
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+        }
+    }
+
+    #[test]
+    fn test_u31_mul_many() {
+        let mut prng = ChaCha20Rng::seed_from_u64(7u64);
+        eprintln!(
+            "u31 mul many (8 elements): {}",
+            u31_mul_many::<BabyBear>(8).len()
+        );
+
+        for w in 1..=4 {
+            for n in [1, 3] {
+                let x = prng.gen_range(0..BabyBear::MOD);
+                let y: Vec<u32> = (0..n).map(|_| prng.gen_range(0..BabyBear::MOD)).collect();
+
+                let script = script! {
+                    for y_i in y.iter() {
+                        { *y_i }
+                    }
+                    { x }
+                    { u31_mul_many_windowed::<BabyBear>(n, w) }
+                    for y_i in y.iter().rev() {
+                        { ((x as u64 * *y_i as u64) % (BabyBear::MOD as u64)) as u32 }
+                        OP_EQUALVERIFY
+                    }
+                    OP_TRUE
+                };
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+        }
+
+        let x = prng.gen_range(0..M31::MOD);
+        let y: Vec<u32> = (0..4).map(|_| prng.gen_range(0..M31::MOD)).collect();
+
+        let script = script! {
+            for y_i in y.iter() {
+                { *y_i }
+            }
+            { x }
+            { u31_mul_many::<M31>(4) }
+            for y_i in y.iter().rev() {
+                { ((x as u64 * *y_i as u64) % (M31::MOD as u64)) as u32 }
+                OP_EQUALVERIFY
+            }
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
     #[test]
     fn test_u31_mul_by_constant() {
         let mut prng = ChaCha20Rng::seed_from_u64(6u64);
@@ -531,4 +961,116 @@ mod test {
             assert!(exec_result.success);
         }
     }
+
+    #[test]
+    fn test_u31_batch_inv() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!(
+            "u31 batch inv (8 elements): {}",
+            u31_batch_inv::<BabyBear>(8).len()
+        );
+
+        for n in [1, 2, 8] {
+            let a: Vec<u32> = (0..n).map(|_| prng.gen_range(1..M31::MOD)).collect();
+            let prod = a
+                .iter()
+                .fold(1u64, |acc, x| acc * (*x as u64) % (M31::MOD as u64));
+
+            let script = script! {
+                { u31_inv_native::<M31>(prod as u32) }
+                for x in a.iter() {
+                    { *x }
+                }
+                { u31_batch_inv::<M31>(n) }
+                for x in a.iter().rev() {
+                    { u31_inv_native::<M31>(*x) }
+                    OP_EQUALVERIFY
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            let a: Vec<u32> = (0..n).map(|_| prng.gen_range(1..BabyBear::MOD)).collect();
+            let prod = a
+                .iter()
+                .fold(1u64, |acc, x| acc * (*x as u64) % (BabyBear::MOD as u64));
+
+            let script = script! {
+                { u31_inv_native::<BabyBear>(prod as u32) }
+                for x in a.iter() {
+                    { *x }
+                }
+                { u31_batch_inv::<BabyBear>(n) }
+                for x in a.iter().rev() {
+                    { u31_inv_native::<BabyBear>(*x) }
+                    OP_EQUALVERIFY
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        // a wrong hint must be rejected
+        let script = script! {
+            2 3 5
+            { u31_batch_inv::<BabyBear>(2) }
+            OP_2DROP
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
+
+    fn test_u31_batch_inv_hinted_generic<M: U31Config>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+        let run = |a: &[u32], hints: &[u32]| {
+            let script = script! {
+                for x in a.iter() {
+                    { *x }
+                }
+                for x in hints.iter() {
+                    { *x }
+                }
+                { u31_batch_inv_hinted::<M>(a.len()) }
+                for x in hints.iter().rev() {
+                    { *x }
+                    OP_EQUALVERIFY
+                }
+                OP_TRUE
+            };
+            execute_script(script).success
+        };
+
+        for n in [1, 2, 8] {
+            let a: Vec<u32> = (0..n).map(|_| prng.gen_range(1..M::MOD)).collect();
+            let a_inv: Vec<u32> = a.iter().map(|x| u31_inv_native::<M>(*x)).collect();
+            assert!(run(&a, &a_inv));
+
+            // a forged inverse in any position must be rejected
+            let mut forged = a_inv.clone();
+            let i = prng.gen_range(0..n);
+            forged[i] = (forged[i] + 1) % M::MOD;
+            assert!(!run(&a, &forged));
+        }
+
+        // the right inverses in the wrong order
+        let swapped = [u31_inv_native::<M>(5), u31_inv_native::<M>(3)];
+        assert!(!run(&[3, 5], &swapped));
+
+        // zero has no inverse
+        assert!(!run(&[0], &[0]));
+    }
+
+    #[test]
+    fn test_u31_batch_inv_hinted() {
+        eprintln!(
+            "u31 batch inv hinted (8 elements): {}",
+            u31_batch_inv_hinted::<BabyBear>(8).len()
+        );
+        test_u31_batch_inv_hinted_generic::<M31>(0u64);
+        test_u31_batch_inv_hinted_generic::<BabyBear>(1u64);
+    }
 }
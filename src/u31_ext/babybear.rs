@@ -50,12 +50,13 @@ impl U31ExtConfig for BabyBear4 {
 #[cfg(test)]
 mod test {
     use crate::{
-        u31ext_add, u31ext_copy, u31ext_double, u31ext_equalverify, u31ext_mul, u31ext_mul_u31,
+        u31ext_add, u31ext_batch_inv, u31ext_batch_inv_hinted, u31ext_copy, u31ext_double,
+        u31ext_equalverify, u31ext_many_mul_u31, u31ext_mul, u31ext_mul_u31,
         u31ext_mul_u31_by_constant, u31ext_roll, u31ext_sub,
     };
     use bitvm::treepp::*;
     use core::ops::{Add, Mul, Neg};
-    use rand::SeedableRng;
+    use rand::{Rng, SeedableRng};
     use rand_chacha::ChaCha20Rng;
     use risc0_core::field::baby_bear::{BabyBearElem, BabyBearExtElem};
     use risc0_core::field::Elem;
@@ -187,6 +188,36 @@ mod test {
         assert!(exec_result.success);
     }
 
+    #[test]
+    fn test_u31ext_many_mul_u31() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+
+        for n in [2, 12] {
+            let a: Vec<BabyBearExtElem> =
+                (0..n).map(|_| BabyBearExtElem::random(&mut prng)).collect();
+            let b = BabyBearElem::random(&mut prng);
+
+            let script = script! {
+                for x in a.iter() {
+                    for y in x.elems().iter().rev() {
+                        { y.as_u32() }
+                    }
+                }
+                { b.as_u32() }
+                { u31ext_many_mul_u31::<BabyBear4>(n) }
+                for x in a.iter().rev() {
+                    for y in (*x * b).elems().iter().rev() {
+                        { y.as_u32() }
+                    }
+                    { u31ext_equalverify::<BabyBear4>() }
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
     #[test]
     fn test_u31ext_mul_u31_by_constant() {
         let mut prng = ChaCha20Rng::seed_from_u64(0u64);
@@ -277,4 +308,74 @@ mod test {
         let exec_result = execute_script(script);
         assert!(exec_result.success);
     }
+
+    #[test]
+    fn test_u31ext_batch_inv() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!(
+            "babybear4 batch inv (8 elements): {}",
+            u31ext_batch_inv::<BabyBear4>(8).len()
+        );
+        eprintln!(
+            "babybear4 batch inv hinted (8 elements): {}",
+            u31ext_batch_inv_hinted::<BabyBear4>(8).len()
+        );
+
+        for n in [1, 2, 5] {
+            let a: Vec<BabyBearExtElem> =
+                (0..n).map(|_| BabyBearExtElem::random(&mut prng)).collect();
+            let a_inv: Vec<BabyBearExtElem> = a.iter().map(|x| x.inv()).collect();
+            let prod_inv = a.iter().fold(BabyBearExtElem::ONE, |acc, x| acc * *x).inv();
+
+            let script = script! {
+                for x in std::iter::once(&prod_inv).chain(a.iter()) {
+                    for y in x.elems().iter().rev() {
+                        { y.as_u32() }
+                    }
+                }
+                { u31ext_batch_inv::<BabyBear4>(n) }
+                for x in a_inv.iter().rev() {
+                    for y in x.elems().iter().rev() {
+                        { y.as_u32() }
+                    }
+                    { u31ext_equalverify::<BabyBear4>() }
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            let hinted = |hints: &[BabyBearExtElem]| {
+                let script = script! {
+                    for x in a.iter().chain(hints.iter()) {
+                        for y in x.elems().iter().rev() {
+                            { y.as_u32() }
+                        }
+                    }
+                    { u31ext_batch_inv_hinted::<BabyBear4>(n) }
+                    for x in hints.iter().rev() {
+                        for y in x.elems().iter().rev() {
+                            { y.as_u32() }
+                        }
+                        { u31ext_equalverify::<BabyBear4>() }
+                    }
+                    OP_TRUE
+                };
+                execute_script(script).success
+            };
+            assert!(hinted(&a_inv));
+
+            // a forged inverse in any position must be rejected
+            let mut forged = a_inv.clone();
+            let i = prng.gen_range(0..n);
+            forged[i] += BabyBearExtElem::ONE;
+            assert!(!hinted(&forged));
+
+            if n > 1 {
+                let mut swapped = a_inv.clone();
+                swapped.swap(0, 1);
+                assert!(!hinted(&swapped));
+            }
+        }
+    }
 }
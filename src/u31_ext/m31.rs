@@ -34,13 +34,14 @@ impl U31ExtConfig for QM31 {
 #[cfg(test)]
 mod test {
     use crate::{
-        u31ext_add, u31ext_copy, u31ext_double, u31ext_equalverify, u31ext_mul, u31ext_mul_u31,
+        u31ext_add, u31ext_batch_inv, u31ext_batch_inv_hinted, u31ext_copy, u31ext_double,
+        u31ext_equalverify, u31ext_many_mul_u31, u31ext_mul, u31ext_mul_u31,
         u31ext_mul_u31_by_constant, u31ext_roll, u31ext_sub,
     };
     use bitvm::treepp::*;
     use core::ops::{Add, Mul, Neg};
     use p3_field::extension::Complex;
-    use p3_field::{AbstractExtensionField, AbstractField, PrimeField32};
+    use p3_field::{AbstractExtensionField, AbstractField, Field, PrimeField32};
     use rand::{Rng, SeedableRng};
     use rand_chacha::ChaCha20Rng;
 
@@ -215,6 +216,54 @@ mod test {
         assert!(exec_result.success);
     }
 
+    #[test]
+    fn test_u31ext_many_mul_u31() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!(
+            "qm31 many mul_by_m31 (4 elements): {}",
+            u31ext_many_mul_u31::<QM31>(4).len()
+        );
+        eprintln!(
+            "qm31 many mul_by_m31 (12 elements): {}",
+            u31ext_many_mul_u31::<QM31>(12).len()
+        );
+
+        let push = |x: &F| {
+            let x: &[Complex<p3_mersenne_31::Mersenne31>] = x.as_base_slice();
+            script! {
+                { x[1].imag().as_canonical_u32() }
+                { x[1].real().as_canonical_u32() }
+                { x[0].imag().as_canonical_u32() }
+                { x[0].real().as_canonical_u32() }
+            }
+        };
+
+        // 3 and 12 elements, with the copies of the bits on the altstack and on the stack
+        for n in [3, 12] {
+            let a: Vec<F> = (0..n).map(|_| rng.gen::<F>()).collect();
+            let b = rng.gen::<p3_mersenne_31::Mersenne31>();
+            let b_ext = F::new(
+                Complex::<p3_mersenne_31::Mersenne31>::new(b, p3_mersenne_31::Mersenne31::zero()),
+                Complex::<p3_mersenne_31::Mersenne31>::zero(),
+            );
+
+            let script = script! {
+                for x in a.iter() {
+                    { push(x) }
+                }
+                { b.as_canonical_u32() }
+                { u31ext_many_mul_u31::<QM31>(n) }
+                for x in a.iter().rev() {
+                    { push(&(*x * b_ext)) }
+                    { u31ext_equalverify::<QM31>() }
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
     #[test]
     fn test_u31ext_mul_u31_by_constant() {
         let mut rng = ChaCha20Rng::seed_from_u64(0u64);
@@ -338,4 +387,90 @@ mod test {
         let exec_result = execute_script(script);
         assert!(exec_result.success);
     }
+
+    #[test]
+    fn test_u31ext_batch_inv() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!(
+            "qm31 batch inv (8 elements): {}",
+            u31ext_batch_inv::<QM31>(8).len()
+        );
+        eprintln!(
+            "qm31 batch inv hinted (8 elements): {}",
+            u31ext_batch_inv_hinted::<QM31>(8).len()
+        );
+
+        let push = |x: &F| {
+            let x: &[Complex<p3_mersenne_31::Mersenne31>] = x.as_base_slice();
+            script! {
+                { x[1].imag().as_canonical_u32() }
+                { x[1].real().as_canonical_u32() }
+                { x[0].imag().as_canonical_u32() }
+                { x[0].real().as_canonical_u32() }
+            }
+        };
+
+        for n in [1, 2, 5] {
+            let a: Vec<F> = (0..n).map(|_| rng.gen::<F>()).collect();
+            let a_inv: Vec<F> = a.iter().map(|x| x.inverse()).collect();
+            let prod_inv = a.iter().fold(F::one(), |acc, x| acc * *x).inverse();
+
+            let script = script! {
+                { push(&prod_inv) }
+                for x in a.iter() {
+                    { push(x) }
+                }
+                { u31ext_batch_inv::<QM31>(n) }
+                for x in a_inv.iter().rev() {
+                    { push(x) }
+                    { u31ext_equalverify::<QM31>() }
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            let hinted = |hints: &[F]| {
+                let script = script! {
+                    for x in a.iter().chain(hints.iter()) {
+                        { push(x) }
+                    }
+                    { u31ext_batch_inv_hinted::<QM31>(n) }
+                    for x in hints.iter().rev() {
+                        { push(x) }
+                        { u31ext_equalverify::<QM31>() }
+                    }
+                    OP_TRUE
+                };
+                execute_script(script).success
+            };
+            assert!(hinted(&a_inv));
+
+            // a forged inverse in any position must be rejected
+            let mut forged = a_inv.clone();
+            let i = rng.gen_range(0..n);
+            forged[i] += F::one();
+            assert!(!hinted(&forged));
+
+            if n > 1 {
+                let mut swapped = a_inv.clone();
+                swapped.swap(0, 1);
+                assert!(!hinted(&swapped));
+            }
+        }
+
+        // a wrong hint must be rejected
+        let a = rng.gen::<F>();
+        let script = script! {
+            { push(&a) }
+            { push(&a) }
+            { u31ext_batch_inv::<QM31>(1) }
+            for _ in 0..4 {
+                OP_DROP
+            }
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(!exec_result.success);
+    }
 }
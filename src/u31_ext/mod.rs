@@ -48,6 +48,17 @@ pub fn u31ext_equalverify<C: U31ExtConfig>() -> Script {
     }
 }
 
+// Input: a (u31ext)
+// Output: nothing, fails unless a = 1
+pub fn u31ext_equalverify_one<C: U31ExtConfig>() -> Script {
+    script! {
+        1 OP_EQUALVERIFY
+        for _ in 1..C::DEGREE {
+            0 OP_EQUALVERIFY
+        }
+    }
+}
+
 pub fn u31ext_sub<C: U31ExtConfig>() -> Script {
     script! {
         { unroll(C::DEGREE - 1, |i| {
@@ -86,51 +97,73 @@ pub fn u31ext_mul_u31<C: U31ExtConfig>() -> Script {
     //
     // u31
     // e
+    u31ext_many_mul_u31::<C>(1)
+}
+
+// Up to this many limbs, u31ext_many_mul_u31 keeps all the copies of the bits on the altstack.
+const MANY_MUL_U31_ALTSTACK_LIMBS: usize = 8;
+
+// Input: x_0, ..., x_{n-1} (u31ext), e (u31)
+// Output: x_0 * e, ..., x_{n-1} * e
+//
+// The bit decomposition of e is done once and copied for each of the n * DEGREE limbs. Up to
+// 8 limbs, all the copies are made first and sit on the altstack together, 31 elements per
+// limb. Beyond, so that any n fits in the 1000-element stack limit, the bits stay on the stack
+// and are copied to the altstack right before each limb, which costs 3 more bytes per limb but
+// never holds more than 62 elements besides the inputs and the products.
+pub fn u31ext_many_mul_u31<C: U31ExtConfig>(n: usize) -> Script {
+    let num_limbs = n * C::DEGREE as usize;
+    assert!(num_limbs >= 1);
+
+    if num_limbs > MANY_MUL_U31_ALTSTACK_LIMBS {
+        return script! {
+            { u31_to_bits() }
+
+            // from the top limb to the deepest one, keeping the products on the altstack
+            for _ in 1..num_limbs {
+                for _ in 0..31 {
+                    30 OP_PICK
+                }
+                for _ in 0..31 {
+                    OP_TOALTSTACK
+                }
+                31 OP_ROLL
+                { u31_mul_common::<C::BaseFieldConfig>() }
+                OP_TOALTSTACK
+            }
+
+            for _ in 0..31 {
+                OP_TOALTSTACK
+            }
+            { u31_mul_common::<C::BaseFieldConfig>() }
+            for _ in 1..num_limbs {
+                OP_FROMALTSTACK
+            }
+        };
+    }
 
     script! {
         { u31_to_bits() }
 
-        // duplicate 3 times
-        for _ in 0..31 {
-            30 OP_PICK
-        }
-        for _ in 0..31 {
-            OP_TOALTSTACK
-        }
-
-        for _ in 0..31 {
-            30 OP_PICK
-        }
-        for _ in 0..31 {
-            OP_TOALTSTACK
+        // duplicate num_limbs - 1 times
+        for _ in 1..num_limbs {
+            for _ in 0..31 {
+                30 OP_PICK
+            }
+            for _ in 0..31 {
+                OP_TOALTSTACK
+            }
         }
 
-        for _ in 0..31 {
-            30 OP_PICK
-        }
         for _ in 0..31 {
             OP_TOALTSTACK
         }
 
-        for _ in 0..31 {
-            OP_TOALTSTACK
+        // from the deepest limb to the top one
+        for _ in 0..num_limbs {
+            { num_limbs - 1 } OP_ROLL
+            { u31_mul_common::<C::BaseFieldConfig>() }
         }
-
-        // d
-        3 OP_ROLL
-        { u31_mul_common::<C::BaseFieldConfig>() }
-
-        // c
-        3 OP_ROLL
-        { u31_mul_common::<C::BaseFieldConfig>() }
-
-        // b
-        3 OP_ROLL
-        { u31_mul_common::<C::BaseFieldConfig>() }
-
-        // a
-        3 OP_ROLL
-        { u31_mul_common::<C::BaseFieldConfig>() }
     }
 }
 
@@ -141,14 +174,12 @@ pub fn u31ext_mul_u31_by_constant<C: U31ExtConfig>(constant: u32) -> Script {
     // d, c, b, a
 
     script! {
-        OP_TOALTSTACK OP_TOALTSTACK OP_TOALTSTACK
-        { u31_mul_by_constant::<C::BaseFieldConfig>(constant) }
-        OP_FROMALTSTACK
-        { u31_mul_by_constant::<C::BaseFieldConfig>(constant) }
-        OP_FROMALTSTACK
-        { u31_mul_by_constant::<C::BaseFieldConfig>(constant) }
-        OP_FROMALTSTACK
+        { unroll(C::DEGREE - 1, |_| script! { OP_TOALTSTACK }) }
         { u31_mul_by_constant::<C::BaseFieldConfig>(constant) }
+        { unroll(C::DEGREE - 1, |_| script! {
+            OP_FROMALTSTACK
+            { u31_mul_by_constant::<C::BaseFieldConfig>(constant) }
+        }) }
     }
 }
 
@@ -187,3 +218,66 @@ pub fn u31ext_roll<C: U31ExtConfig>(offset: usize) -> Script {
         }
     }
 }
+
+// Input: h = (a_0 * ... * a_{n-1})^{-1} (supplied by the prover), a_0, ..., a_{n-1} (u31ext)
+// Output: a_0^{-1}, ..., a_{n-1}^{-1}
+//
+// Same as u31_batch_inv, with 3(n - 1) + 1 extension field multiplications and one check of
+// h against the product.
+pub fn u31ext_batch_inv<C: U31ExtConfig>(n: usize) -> Script {
+    assert!(n >= 1);
+
+    script! {
+        // prefix products c_i = a_0 * ... * a_i
+        { u31ext_copy::<C>(n - 1) }
+        for _ in 1..n {
+            { u31ext_copy::<C>(0) }
+            { u31ext_copy::<C>(n) }
+            { u31ext_mul::<C>() }
+        }
+
+        // check c_{n-1} * h = 1
+        { u31ext_copy::<C>(2 * n) }
+        { u31ext_mul::<C>() }
+        { u31ext_equalverify_one::<C>() }
+        { u31ext_roll::<C>(2 * n - 1) }
+
+        for i in (1..n).rev() {
+            // a_i^{-1} = c_{i-1} * c_i^{-1}
+            { u31ext_roll::<C>(1) }
+            { u31ext_copy::<C>(1) }
+            { u31ext_mul::<C>() }
+            { u31ext_toaltstack::<C>() }
+
+            // c_{i-1}^{-1} = a_i * c_i^{-1}
+            { u31ext_roll::<C>(i) }
+            { u31ext_mul::<C>() }
+        }
+        { u31ext_roll::<C>(1) }
+        for _ in 0..C::DEGREE {
+            OP_DROP
+        }
+        for _ in 1..n {
+            { u31ext_fromaltstack::<C>() }
+        }
+    }
+}
+
+// Input: a_0, ..., a_{n-1}, b_0, ..., b_{n-1} (u31ext, b_i = a_i^{-1} supplied by the prover)
+// Output: b_0, ..., b_{n-1}
+//
+// Same as u31_batch_inv_hinted, with n extension field multiplications.
+pub fn u31ext_batch_inv_hinted<C: U31ExtConfig>(n: usize) -> Script {
+    script! {
+        for k in 0..n {
+            { u31ext_copy::<C>(0) }
+            { u31ext_roll::<C>(n - k + 1) }
+            { u31ext_mul::<C>() }
+            { u31ext_equalverify_one::<C>() }
+            { u31ext_toaltstack::<C>() }
+        }
+        for _ in 0..n {
+            { u31ext_fromaltstack::<C>() }
+        }
+    }
+}
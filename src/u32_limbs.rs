@@ -0,0 +1,228 @@
+use crate::{
+    u31::u31_to_limbs, u31_add, u31_add_v31, u31_double, u31_mul_by_constant, u31_to_v31, U31Config,
+};
+use bitvm::treepp::*;
+
+// BitVM's `u32_std` keeps a u32 as its four bytes, with the most significant byte at the bottom
+// (see `u32_push`).
+
+// Input: a
+// Output: a as a u32 of BitVM, i.e., (a >> 24) & 255, (a >> 16) & 255, (a >> 8) & 255, a & 255
+pub fn u31_to_u32_limbs() -> Script {
+    u31_to_limbs(8)
+}
+
+// Input: a u32 of BitVM, i.e., b3, b2, b1, b0 with bytes b_i in [0, 256)
+// Output: (b3 * 2^24 + b2 * 2^16 + b1 * 2^8 + b0) mod MOD
+pub fn u31_from_u32_limbs<M: U31Config>() -> Script {
+    script! {
+        3 OP_ROLL
+        OP_DUP 127 OP_GREATERTHAN
+        OP_DUP OP_TOALTSTACK
+        OP_IF 128 OP_SUB OP_ENDIF
+        for i in 0..3 {
+            for _ in 0..8 {
+                OP_DUP OP_ADD
+            }
+            { 3 - i } OP_ROLL OP_ADD
+        }
+        OP_FROMALTSTACK
+        { u31_fold_top_bit::<M>() }
+    }
+}
+
+// Input: x_hi, x_lo, the two 16-bit halves of a 32-bit value x
+// Output: x mod MOD
+pub fn u32_reduce<M: U31Config>() -> Script {
+    script! {
+        OP_SWAP
+        OP_DUP 32767 OP_GREATERTHAN
+        OP_DUP OP_TOALTSTACK
+        OP_IF 32768 OP_SUB OP_ENDIF
+        for _ in 0..16 {
+            OP_DUP OP_ADD
+        }
+        OP_ADD
+        OP_FROMALTSTACK
+        { u31_fold_top_bit::<M>() }
+    }
+}
+
+// Input: x3, x2, x1, x0, the 16-bit limbs of a 64-bit value x (x0 being the lowest)
+// Output: x mod MOD
+pub fn u64_reduce<M: U31Config>() -> Script {
+    // 2^32 mod MOD, which is 2 for M31
+    let shift = if is_m31::<M>() {
+        u31_double::<M>()
+    } else {
+        u31_mul_by_constant::<M>(((1u64 << 32) % M::MOD as u64) as u32)
+    };
+
+    script! {
+        { u32_reduce::<M>() }
+        OP_TOALTSTACK
+        { u32_reduce::<M>() }
+        { shift }
+        OP_FROMALTSTACK
+        { u31_add::<M>() }
+    }
+}
+
+fn is_m31<M: U31Config>() -> bool {
+    M::MOD == (1 << 31) - 1
+}
+
+// Input: low, h, with low < 2^31 and h in {0, 1}
+// Output: (low + h * 2^31) mod MOD
+//
+// A value of 2^31 or more does not fit in a script number, so the top bit h of x is split off
+// and folded back in as h * (2^31 mod MOD). As 2^31 < 2 * MOD, low needs at most one subtraction.
+// For M31, 2^31 mod MOD = 1, so low - MOD can be added to h as in u31_add.
+fn u31_fold_top_bit<M: U31Config>() -> Script {
+    assert!(M::MOD > 1 << 30 && M::MOD < 1 << 31);
+
+    if is_m31::<M>() {
+        script! {
+            OP_SWAP
+            { u31_to_v31::<M>() }
+            { u31_add_v31::<M>() }
+        }
+    } else {
+        script! {
+            OP_SWAP
+            OP_DUP { M::MOD } OP_GREATERTHANOREQUAL
+            OP_IF { M::MOD } OP_SUB OP_ENDIF
+            OP_SWAP
+            OP_IF { (1u32 << 31) % M::MOD } { u31_add::<M>() } OP_ENDIF
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        u31_from_u32_limbs, u31_to_u32_limbs, u32_reduce, u64_reduce, BabyBear, U31Config, M31,
+    };
+    use bitvm::treepp::*;
+    use bitvm::u32::u32_std::{u32_equalverify, u32_push};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_u31_to_u32_limbs() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        eprintln!("u31 to u32 limbs: {}", u31_to_u32_limbs().len());
+
+        let mut values = vec![0, 1, 255, 256, M31::MOD - 1, BabyBear::MOD - 1];
+        for _ in 0..100 {
+            values.push(prng.gen::<u32>() % M31::MOD);
+        }
+
+        for a in values {
+            let script = script! {
+                { a }
+                { u31_to_u32_limbs() }
+                { u32_push(a) }
+                { u32_equalverify() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    fn test_u31_from_u32_limbs_generic<M: U31Config>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+        let mut values = vec![0, 1, M::MOD - 1, M::MOD, (1 << 31) - 1, 1 << 31, u32::MAX];
+        for _ in 0..100 {
+            values.push(prng.gen::<u32>());
+        }
+
+        for x in values {
+            let script = script! {
+                { u32_push(x) }
+                { u31_from_u32_limbs::<M>() }
+                { x % M::MOD }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        for _ in 0..10 {
+            let a = prng.gen_range(0..M::MOD);
+            let script = script! {
+                { a }
+                { u31_to_u32_limbs() }
+                { u31_from_u32_limbs::<M>() }
+                { a }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_u31_from_u32_limbs() {
+        eprintln!("m31 from u32 limbs: {}", u31_from_u32_limbs::<M31>().len());
+        eprintln!(
+            "babybear from u32 limbs: {}",
+            u31_from_u32_limbs::<BabyBear>().len()
+        );
+
+        test_u31_from_u32_limbs_generic::<M31>(0u64);
+        test_u31_from_u32_limbs_generic::<BabyBear>(1u64);
+    }
+
+    fn test_u32_reduce_generic<M: U31Config>(seed: u64) {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+        let mut values = vec![0, 1, M::MOD - 1, M::MOD, (1 << 31) - 1, 1 << 31, u32::MAX];
+        for _ in 0..100 {
+            values.push(prng.gen::<u32>());
+        }
+
+        for x in values {
+            let script = script! {
+                { x >> 16 }
+                { x & 0xffff }
+                { u32_reduce::<M>() }
+                { x % M::MOD }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        let mut values = vec![0, u32::MAX as u64, 1 << 32, u64::MAX];
+        for _ in 0..20 {
+            values.push(prng.gen::<u64>());
+        }
+
+        for x in values {
+            let script = script! {
+                for i in (0..4).rev() {
+                    { ((x >> (16 * i)) & 0xffff) as u32 }
+                }
+                { u64_reduce::<M>() }
+                { (x % M::MOD as u64) as u32 }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_u32_reduce() {
+        eprintln!("m31 u32 reduce: {}", u32_reduce::<M31>().len());
+        eprintln!("babybear u32 reduce: {}", u32_reduce::<BabyBear>().len());
+        eprintln!("m31 u64 reduce: {}", u64_reduce::<M31>().len());
+        eprintln!("babybear u64 reduce: {}", u64_reduce::<BabyBear>().len());
+
+        test_u32_reduce_generic::<M31>(0u64);
+        test_u32_reduce_generic::<BabyBear>(1u64);
+    }
+}
@@ -0,0 +1,448 @@
+use crate::{
+    u31_add, u31_inv_native, u31_pow_native, u31_sub, u31ext_add, u31ext_copy, u31ext_double,
+    u31ext_equalverify_one, u31ext_fromaltstack, u31ext_mul, u31ext_mul_u31_by_constant,
+    u31ext_sub, u31ext_toaltstack, BabyBear, BabyBear4, U31Config, U31ExtConfig, M31, QM31,
+};
+use bitvm::treepp::*;
+
+// generator of the multiplicative subgroup of order 2^27 in BabyBear
+const BABYBEAR_TWO_ADIC_GENERATOR: u32 = 440564289;
+
+// generator of the circle group of order 2^31 over M31
+const M31_CIRCLE_GENERATOR: (u32, u32) = (2, 1268011823);
+
+pub trait DomainConfig: U31ExtConfig {
+    type CosetShift: Copy;
+    // the number of extension elements that make a point: z, or p.x and p.y
+    const POINT_SIZE: usize;
+
+    fn vanishing_impl(log_n: u32, coset_shift: Self::CosetShift) -> Script;
+    fn lagrange_selector_impl(log_n: u32, coset_shift: Self::CosetShift, row: usize) -> Script;
+}
+
+pub fn vanishing_eval<C: DomainConfig>(log_n: u32, coset_shift: C::CosetShift) -> Script {
+    C::vanishing_impl(log_n, coset_shift)
+}
+
+pub fn lagrange_first_row<C: DomainConfig>(log_n: u32, coset_shift: C::CosetShift) -> Script {
+    C::lagrange_selector_impl(log_n, coset_shift, 0)
+}
+
+pub fn lagrange_last_row<C: DomainConfig>(log_n: u32, coset_shift: C::CosetShift) -> Script {
+    C::lagrange_selector_impl(log_n, coset_shift, (1 << log_n) - 1)
+}
+
+fn babybear_subgroup_generator(log_n: u32) -> u32 {
+    assert!(log_n <= 27);
+    u31_pow_native::<BabyBear>(BABYBEAR_TWO_ADIC_GENERATOR, 1 << (27 - log_n))
+}
+
+fn m31_circle_add(a: (u32, u32), b: (u32, u32)) -> (u32, u32) {
+    let m = M31::MOD as u64;
+    let (ax, ay) = (a.0 as u64, a.1 as u64);
+    let (bx, by) = (b.0 as u64, b.1 as u64);
+    (
+        ((ax * bx % m + m - ay * by % m) % m) as u32,
+        ((ax * by + ay * bx) % m) as u32,
+    )
+}
+
+fn m31_circle_neg(a: (u32, u32)) -> (u32, u32) {
+    (a.0, (M31::MOD - a.1) % M31::MOD)
+}
+
+fn m31_circle_mul(mut a: (u32, u32), mut k: u64) -> (u32, u32) {
+    let mut res = (1, 0);
+    while k > 0 {
+        if k & 1 == 1 {
+            res = m31_circle_add(res, a);
+        }
+        a = m31_circle_add(a, a);
+        k >>= 1;
+    }
+    res
+}
+
+fn m31_circle_subgroup_generator(log_n: u32) -> (u32, u32) {
+    assert!(log_n <= 31);
+    m31_circle_mul(M31_CIRCLE_GENERATOR, 1 << (31 - log_n))
+}
+
+impl DomainConfig for BabyBear4 {
+    // the domain is shift * <g> where g has order 2^log_n
+    type CosetShift = u32;
+    const POINT_SIZE: usize = 1;
+
+    // Input: z (babybear4)
+    // Output: (z / shift)^n - 1
+    fn vanishing_impl(log_n: u32, coset_shift: u32) -> Script {
+        let shift_inv_pow_n =
+            u31_pow_native::<BabyBear>(u31_inv_native::<BabyBear>(coset_shift), 1 << log_n);
+
+        let scale = if shift_inv_pow_n != 1 {
+            u31ext_mul_u31_by_constant::<Self>(shift_inv_pow_n)
+        } else {
+            script! {}
+        };
+
+        script! {
+            for _ in 0..log_n {
+                { u31ext_copy::<Self>(0) }
+                { u31ext_mul::<Self>() }
+            }
+            { scale }
+            1 { u31_sub::<BabyBear>() }
+        }
+    }
+
+    // Input:
+    //      hint = (z / shift - g^row)^{-1} (babybear4)
+    //      z (babybear4)
+    // Output: Z_H(z) / (z / shift - g^row), which is zero on the domain except at row
+    //
+    // As in Plonky3, the selector is not normalized by 1 / n.
+    fn lagrange_selector_impl(log_n: u32, coset_shift: u32, row: usize) -> Script {
+        let shift_inv = u31_inv_native::<BabyBear>(coset_shift);
+        let point = u31_pow_native::<BabyBear>(babybear_subgroup_generator(log_n), row as u64);
+
+        let scale = if shift_inv != 1 {
+            u31ext_mul_u31_by_constant::<Self>(shift_inv)
+        } else {
+            script! {}
+        };
+
+        script! {
+            { u31ext_copy::<Self>(0) }
+            { scale }
+            { point } { u31_sub::<BabyBear>() }
+
+            // check the hinted inverse
+            { u31ext_copy::<Self>(2) }
+            { u31ext_mul::<Self>() }
+            { u31ext_equalverify_one::<Self>() }
+
+            { Self::vanishing_impl(log_n, coset_shift) }
+            { u31ext_mul::<Self>() }
+        }
+    }
+}
+
+impl DomainConfig for QM31 {
+    // the domain is the circle coset initial + <step> where step has order 2^log_n
+    type CosetShift = (u32, u32);
+    const POINT_SIZE: usize = 2;
+
+    // Input: p.x (qm31), p.y (qm31)
+    // Output: the x-coordinate of p - initial + step / 2, doubled log_n - 1 times with 2x^2 - 1
+    fn vanishing_impl(log_n: u32, coset_shift: (u32, u32)) -> Script {
+        assert!(log_n >= 1);
+        let (qx, qy) = m31_circle_add(
+            m31_circle_subgroup_generator(log_n + 1),
+            m31_circle_neg(coset_shift),
+        );
+
+        script! {
+            { u31ext_mul_u31_by_constant::<Self>(qy) }
+            { u31ext_toaltstack::<Self>() }
+            { u31ext_mul_u31_by_constant::<Self>(qx) }
+            { u31ext_fromaltstack::<Self>() }
+            { u31ext_sub::<Self>() }
+            for _ in 1..log_n {
+                { u31ext_copy::<Self>(0) }
+                { u31ext_mul::<Self>() }
+                { u31ext_double::<Self>() }
+                1 { u31_sub::<M31>() }
+            }
+        }
+    }
+
+    // Input:
+    //      hint = h.y^{-1} (qm31), where h = p - (initial + row * step)
+    //      p.x (qm31), p.y (qm31)
+    // Output: Z(p) * (1 + h.x) / h.y, which is Z(p) divided by Stwo's point_vanishing
+    fn lagrange_selector_impl(log_n: u32, coset_shift: (u32, u32), row: usize) -> Script {
+        let point = m31_circle_add(
+            coset_shift,
+            m31_circle_mul(m31_circle_subgroup_generator(log_n), row as u64),
+        );
+        let (a, b) = m31_circle_neg(point);
+
+        script! {
+            // h.y = b * p.x + a * p.y
+            { u31ext_copy::<Self>(1) }
+            { u31ext_mul_u31_by_constant::<Self>(b) }
+            { u31ext_copy::<Self>(1) }
+            { u31ext_mul_u31_by_constant::<Self>(a) }
+            { u31ext_add::<Self>() }
+
+            // check the hinted inverse
+            { u31ext_copy::<Self>(3) }
+            { u31ext_mul::<Self>() }
+            { u31ext_equalverify_one::<Self>() }
+
+            // 1 + h.x = 1 + a * p.x - b * p.y
+            { u31ext_copy::<Self>(1) }
+            { u31ext_mul_u31_by_constant::<Self>(a) }
+            { u31ext_copy::<Self>(1) }
+            { u31ext_mul_u31_by_constant::<Self>(b) }
+            { u31ext_sub::<Self>() }
+            1 { u31_add::<M31>() }
+            { u31ext_toaltstack::<Self>() }
+
+            { Self::vanishing_impl(log_n, coset_shift) }
+            { u31ext_mul::<Self>() }
+            { u31ext_fromaltstack::<Self>() }
+            { u31ext_mul::<Self>() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{lagrange_first_row, lagrange_last_row};
+    use crate::{u31ext_equalverify, vanishing_eval, BabyBear4, QM31};
+    use bitvm::treepp::*;
+    use p3_field::extension::Complex;
+    use p3_field::{AbstractExtensionField, AbstractField, Field, PrimeField32};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use risc0_core::field::baby_bear::{BabyBearElem, BabyBearExtElem};
+    use risc0_core::field::{Elem, ExtElem};
+
+    use super::*;
+
+    type F = p3_field::extension::BinomialExtensionField<Complex<p3_mersenne_31::Mersenne31>, 2>;
+
+    fn push_babybear4(a: BabyBearExtElem) -> Script {
+        let a: &[BabyBearElem] = a.elems();
+        script! {
+            { a[3].as_u32() } { a[2].as_u32() } { a[1].as_u32() } { a[0].as_u32() }
+        }
+    }
+
+    fn push_qm31(a: F) -> Script {
+        let a: &[Complex<p3_mersenne_31::Mersenne31>] = a.as_base_slice();
+        script! {
+            { a[1].imag().as_canonical_u32() }
+            { a[1].real().as_canonical_u32() }
+            { a[0].imag().as_canonical_u32() }
+            { a[0].real().as_canonical_u32() }
+        }
+    }
+
+    fn qm31_from_m31(a: u32) -> F {
+        F::from_base(Complex::new(
+            p3_mersenne_31::Mersenne31::from_canonical_u32(a),
+            p3_mersenne_31::Mersenne31::zero(),
+        ))
+    }
+
+    #[test]
+    fn test_vanishing_eval_babybear4() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        let log_n = 4;
+        let shift = 31;
+        eprintln!(
+            "babybear4 vanishing (log_n = 4): {}",
+            vanishing_eval::<BabyBear4>(log_n, shift).len()
+        );
+
+        let g = BabyBearElem::new(babybear_subgroup_generator(log_n));
+        for i in 0..1 << log_n {
+            let x = BabyBearExtElem::from_subfield(&(BabyBearElem::new(shift) * g.pow(i)));
+
+            let script = script! {
+                { push_babybear4(x) }
+                { vanishing_eval::<BabyBear4>(log_n, shift) }
+                { push_babybear4(BabyBearExtElem::ZERO) }
+                { u31ext_equalverify::<BabyBear4>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        let z = BabyBearExtElem::random(&mut prng);
+        let expected = (z * BabyBearElem::new(shift).inv()).pow(1 << log_n) - BabyBearExtElem::ONE;
+
+        let script = script! {
+            { push_babybear4(z) }
+            { vanishing_eval::<BabyBear4>(log_n, shift) }
+            { push_babybear4(expected) }
+            { u31ext_equalverify::<BabyBear4>() }
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    fn test_lagrange_selector_babybear4() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0u64);
+        let log_n = 4;
+        let shift = 31;
+
+        let g = BabyBearElem::new(babybear_subgroup_generator(log_n));
+        let shift_inv = BabyBearElem::new(shift).inv();
+        let vanishing = |z: BabyBearExtElem| (z * shift_inv).pow(1 << log_n) - BabyBearExtElem::ONE;
+
+        for (row, selector) in [
+            (0, lagrange_first_row::<BabyBear4>(log_n, shift)),
+            (
+                (1 << log_n) - 1,
+                lagrange_last_row::<BabyBear4>(log_n, shift),
+            ),
+        ] {
+            let denominator =
+                |z: BabyBearExtElem| z * shift_inv - BabyBearExtElem::from_subfield(&g.pow(row));
+
+            let z = BabyBearExtElem::random(&mut prng);
+            let hint = denominator(z).inv();
+            let expected = vanishing(z) * hint;
+
+            let script = script! {
+                { push_babybear4(hint) }
+                { push_babybear4(z) }
+                { selector.clone() }
+                { push_babybear4(expected) }
+                { u31ext_equalverify::<BabyBear4>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            // the selector vanishes on the other rows
+            let z = BabyBearExtElem::from_subfield(&(BabyBearElem::new(shift) * g.pow(5)));
+            let hint = denominator(z).inv();
+
+            let script = script! {
+                { push_babybear4(hint) }
+                { push_babybear4(z) }
+                { selector.clone() }
+                { push_babybear4(BabyBearExtElem::ZERO) }
+                { u31ext_equalverify::<BabyBear4>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_vanishing_eval_qm31() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0u64);
+        let log_n = 4;
+        let initial = m31_circle_mul(M31_CIRCLE_GENERATOR, 12345);
+        eprintln!(
+            "qm31 vanishing (log_n = 4): {}",
+            vanishing_eval::<QM31>(log_n, initial).len()
+        );
+
+        let step = m31_circle_subgroup_generator(log_n);
+        for i in 0..1 << log_n {
+            let (x, y) = m31_circle_add(initial, m31_circle_mul(step, i));
+
+            let script = script! {
+                { push_qm31(qm31_from_m31(x)) }
+                { push_qm31(qm31_from_m31(y)) }
+                { vanishing_eval::<QM31>(log_n, initial) }
+                { push_qm31(F::zero()) }
+                { u31ext_equalverify::<QM31>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+
+        let px = rng.gen::<F>();
+        let py = rng.gen::<F>();
+
+        let (qx, qy) = m31_circle_add(
+            m31_circle_subgroup_generator(log_n + 1),
+            m31_circle_neg(initial),
+        );
+        let mut expected = px * qm31_from_m31(qx) - py * qm31_from_m31(qy);
+        for _ in 1..log_n {
+            expected = expected.square().double() - F::one();
+        }
+
+        let script = script! {
+            { push_qm31(px) }
+            { push_qm31(py) }
+            { vanishing_eval::<QM31>(log_n, initial) }
+            { push_qm31(expected) }
+            { u31ext_equalverify::<QM31>() }
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    fn test_lagrange_selector_qm31() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0u64);
+        let log_n = 4;
+        let initial = m31_circle_mul(M31_CIRCLE_GENERATOR, 12345);
+        let step = m31_circle_subgroup_generator(log_n);
+
+        let vanishing = |px: F, py: F| {
+            let (qx, qy) = m31_circle_add(
+                m31_circle_subgroup_generator(log_n + 1),
+                m31_circle_neg(initial),
+            );
+            let mut res = px * qm31_from_m31(qx) - py * qm31_from_m31(qy);
+            for _ in 1..log_n {
+                res = res.square().double() - F::one();
+            }
+            res
+        };
+
+        for (row, selector) in [
+            (0, lagrange_first_row::<QM31>(log_n, initial)),
+            ((1 << log_n) - 1, lagrange_last_row::<QM31>(log_n, initial)),
+        ] {
+            let (a, b) = m31_circle_neg(m31_circle_add(initial, m31_circle_mul(step, row)));
+            let h = |px: F, py: F| {
+                (
+                    px * qm31_from_m31(a) - py * qm31_from_m31(b),
+                    px * qm31_from_m31(b) + py * qm31_from_m31(a),
+                )
+            };
+
+            let px = rng.gen::<F>();
+            let py = rng.gen::<F>();
+            let (hx, hy) = h(px, py);
+            let hint = hy.inverse();
+            let expected = vanishing(px, py) * (F::one() + hx) * hint;
+
+            let script = script! {
+                { push_qm31(hint) }
+                { push_qm31(px) }
+                { push_qm31(py) }
+                { selector.clone() }
+                { push_qm31(expected) }
+                { u31ext_equalverify::<QM31>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            // the selector vanishes on the other rows, except the antipode where h.y = 0
+            let (x, y) = m31_circle_add(initial, m31_circle_mul(step, row + 1));
+            let (px, py) = (qm31_from_m31(x), qm31_from_m31(y));
+            let hint = h(px, py).1.inverse();
+
+            let script = script! {
+                { push_qm31(hint) }
+                { push_qm31(px) }
+                { push_qm31(py) }
+                { selector.clone() }
+                { push_qm31(F::zero()) }
+                { u31ext_equalverify::<QM31>() }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+}